@@ -0,0 +1,144 @@
+use indexmap::IndexMap;
+use serde::Deserialize;
+use serde_yaml::Value;
+use std::{collections::HashMap, env, error::Error};
+
+/// The `build` key of a service, either a context path or a mapping.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub(crate) enum Build {
+    Context(String),
+    Advanced(BuildConfig),
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub(crate) struct BuildConfig {
+    pub(crate) context: Option<String>,
+    pub(crate) dockerfile: Option<String>,
+    pub(crate) args: Option<KeyValues>,
+    pub(crate) target: Option<String>,
+    pub(crate) labels: Option<KeyValues>,
+    #[serde(default)]
+    pub(crate) no_cache: bool,
+}
+
+/// Build args and labels, written as a `KEY=VALUE` list or a mapping.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub(crate) enum KeyValues {
+    List(Vec<String>),
+    Map(IndexMap<String, Value>),
+}
+
+impl Build {
+    pub(crate) fn into_config(self) -> BuildConfig {
+        match self {
+            Build::Context(context) => BuildConfig {
+                context: Some(context),
+                ..Default::default()
+            },
+            Build::Advanced(config) => config,
+        }
+    }
+}
+
+impl KeyValues {
+    /// Keys listed without a value take it from the environment wizard
+    /// runs in, and are left out when that isn't set either.
+    pub(crate) fn to_map(&self) -> HashMap<String, String> {
+        let pairs: Vec<(String, Option<String>)> = match self {
+            KeyValues::List(list) => list
+                .iter()
+                .map(|item| match item.split_once('=') {
+                    Some((key, value)) => (key.to_string(), Some(value.to_string())),
+                    None => (item.to_string(), None),
+                })
+                .collect(),
+            KeyValues::Map(map) => map
+                .iter()
+                .map(|(key, value)| {
+                    let value = match value {
+                        Value::Null => None,
+                        Value::String(value) => Some(value.clone()),
+                        Value::Number(value) => Some(value.to_string()),
+                        Value::Bool(value) => Some(value.to_string()),
+                        _ => None,
+                    };
+                    (key.clone(), value)
+                })
+                .collect(),
+        };
+        pairs
+            .into_iter()
+            .filter_map(|(key, value)| {
+                let value = value.or_else(|| env::var(&key).ok())?;
+                Some((key, value))
+            })
+            .collect()
+    }
+}
+
+/// Drops every stage after `target` from a multi-stage Dockerfile. Stages
+/// can only build on the ones before them, so this builds the same image
+/// as `docker build --target`.
+pub(crate) fn truncate_to_target(dockerfile: &str, target: &str) -> Result<String, Box<dyn Error>> {
+    let is_from = |line: &str| {
+        line.trim_start()
+            .get(..5)
+            .map(|from| from.eq_ignore_ascii_case("FROM "))
+            .unwrap_or(false)
+    };
+    let names_target = |line: &str| {
+        let words = line.split_whitespace().collect::<Vec<_>>();
+        words.len() >= 4
+            && words[words.len() - 2].eq_ignore_ascii_case("AS")
+            && words[words.len() - 1].eq_ignore_ascii_case(target)
+    };
+    let mut lines = vec![];
+    let mut in_target = false;
+    for line in dockerfile.lines() {
+        if is_from(line) {
+            if in_target {
+                return Ok(lines.join("\n"));
+            }
+            in_target = names_target(line);
+        }
+        lines.push(line);
+    }
+    if in_target {
+        Ok(lines.join("\n"))
+    } else {
+        Err(format!("Target stage {} not found in Dockerfile", target).into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DOCKERFILE: &str = "FROM ruby:3.1 AS base\nRUN bundle install\n\nfrom base as dev\nRUN echo dev\n\nFROM base AS prod\nRUN echo prod";
+
+    #[test]
+    fn keeps_stages_up_to_the_target() {
+        assert_eq!(
+            truncate_to_target(DOCKERFILE, "base").unwrap(),
+            "FROM ruby:3.1 AS base\nRUN bundle install\n"
+        );
+        assert_eq!(
+            truncate_to_target(DOCKERFILE, "DEV").unwrap(),
+            "FROM ruby:3.1 AS base\nRUN bundle install\n\nfrom base as dev\nRUN echo dev\n"
+        );
+    }
+
+    #[test]
+    fn keeps_the_whole_file_for_the_last_stage() {
+        assert_eq!(truncate_to_target(DOCKERFILE, "prod").unwrap(), DOCKERFILE);
+    }
+
+    #[test]
+    fn rejects_unknown_targets() {
+        assert!(truncate_to_target(DOCKERFILE, "test").is_err());
+        // the base image's name doesn't name a stage
+        assert!(truncate_to_target("FROM ruby\nRUN true", "ruby").is_err());
+    }
+}
@@ -0,0 +1,333 @@
+use hyper::Body;
+use std::{
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+use tokio::sync::mpsc::{channel, Sender};
+use tokio_stream::wrappers::ReceiverStream;
+
+/// Patterns from a `.dockerignore` file, matched the way the docker cli
+/// matches them: the last pattern matching a path decides whether it is
+/// excluded, `!` re-includes paths and `**` matches any number of
+/// directories.
+#[derive(Debug, Default)]
+pub(crate) struct DockerIgnore {
+    patterns: Vec<IgnorePattern>,
+}
+
+#[derive(Debug)]
+struct IgnorePattern {
+    segments: Vec<String>,
+    negated: bool,
+}
+
+impl DockerIgnore {
+    pub(crate) fn read(context_dir: &Path) -> io::Result<DockerIgnore> {
+        match fs::read_to_string(context_dir.join(".dockerignore")) {
+            Ok(contents) => Ok(DockerIgnore::parse(&contents)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(DockerIgnore::default()),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub(crate) fn parse(contents: &str) -> DockerIgnore {
+        let patterns = contents
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .filter_map(|line| {
+                let (negated, pattern) = match line.strip_prefix('!') {
+                    Some(pattern) => (true, pattern.trim()),
+                    None => (false, line),
+                };
+                let segments = clean_path(pattern);
+                if segments.is_empty() {
+                    None
+                } else {
+                    Some(IgnorePattern { segments, negated })
+                }
+            })
+            .collect();
+        DockerIgnore { patterns }
+    }
+
+    /// Whether `path`, relative to the context root, is left out of the
+    /// context. A pattern matching one of the parent directories of `path`
+    /// also matches `path`.
+    pub(crate) fn is_excluded(&self, path: &[String]) -> bool {
+        let mut excluded = false;
+        for pattern in &self.patterns {
+            if pattern.negated != excluded {
+                continue;
+            }
+            let matched = (1..=path.len()).any(|length| pattern.matches(&path[..length]));
+            if matched {
+                excluded = !pattern.negated;
+            }
+        }
+        excluded
+    }
+
+    /// Whether an excluded directory has to be walked anyway because a
+    /// negated pattern may re-include something inside it.
+    fn may_include_below(&self, path: &[String]) -> bool {
+        self.patterns
+            .iter()
+            .filter(|pattern| pattern.negated)
+            .any(|pattern| pattern.could_match_below(path))
+    }
+}
+
+impl IgnorePattern {
+    fn matches(&self, path: &[String]) -> bool {
+        match_segments(&self.segments, path)
+    }
+
+    fn could_match_below(&self, path: &[String]) -> bool {
+        let mut pattern = self.segments.iter();
+        for segment in path {
+            match pattern.next() {
+                Some(glob) if glob == "**" => return true,
+                Some(glob) if match_segment(glob, segment) => continue,
+                Some(_) => return false,
+                None => return false,
+            }
+        }
+        true
+    }
+}
+
+/// Splits a path into its segments, dropping empty and `.` segments and
+/// resolving `..` the way `filepath.Clean` does.
+fn clean_path(path: &str) -> Vec<String> {
+    let mut segments: Vec<String> = vec![];
+    for segment in path.split('/') {
+        match segment {
+            "" | "." => {}
+            ".." => {
+                segments.pop();
+            }
+            segment => segments.push(segment.to_string()),
+        }
+    }
+    segments
+}
+
+fn match_segments(pattern: &[String], path: &[String]) -> bool {
+    match pattern.split_first() {
+        None => path.is_empty(),
+        Some((glob, rest)) if glob == "**" => {
+            (0..=path.len()).any(|skip| match_segments(rest, &path[skip..]))
+        }
+        Some((glob, rest)) => match path.split_first() {
+            Some((segment, path)) => match_segment(glob, segment) && match_segments(rest, path),
+            None => false,
+        },
+    }
+}
+
+/// Matches a single path segment against a glob supporting `*`, `?`,
+/// character classes such as `[a-z]` or `[^0-9]` and `\` escapes.
+fn match_segment(glob: &str, segment: &str) -> bool {
+    let glob = glob.chars().collect::<Vec<_>>();
+    let segment = segment.chars().collect::<Vec<_>>();
+    match_chars(&glob, &segment)
+}
+
+fn match_chars(glob: &[char], text: &[char]) -> bool {
+    match glob.first() {
+        None => text.is_empty(),
+        Some('*') => (0..=text.len()).any(|skip| match_chars(&glob[1..], &text[skip..])),
+        Some('?') => !text.is_empty() && match_chars(&glob[1..], &text[1..]),
+        Some('[') => match (text.first(), class_end(glob)) {
+            (Some(c), Some(end)) => {
+                match_class(&glob[1..end], *c) && match_chars(&glob[end + 1..], &text[1..])
+            }
+            _ => false,
+        },
+        Some('\\') if glob.len() > 1 => {
+            text.first() == Some(&glob[1]) && match_chars(&glob[2..], &text[1..])
+        }
+        Some(c) => text.first() == Some(c) && match_chars(&glob[1..], &text[1..]),
+    }
+}
+
+fn class_end(glob: &[char]) -> Option<usize> {
+    glob.iter().skip(2).position(|c| *c == ']').map(|i| i + 2)
+}
+
+fn match_class(class: &[char], c: char) -> bool {
+    let (negated, class) = match class.first() {
+        Some('^') | Some('!') => (true, &class[1..]),
+        _ => (false, class),
+    };
+    let mut matched = false;
+    let mut i = 0;
+    while i < class.len() {
+        if i + 2 < class.len() && class[i + 1] == '-' {
+            matched |= class[i] <= c && c <= class[i + 2];
+            i += 3;
+        } else {
+            matched |= class[i] == c;
+            i += 1;
+        }
+    }
+    matched != negated
+}
+
+/// Streams a gzipped tar of `context_dir` to docker. The Dockerfile is
+/// added as `dockerfile_name` from `dockerfile`, so a Dockerfile that lives
+/// outside the context or was rewritten for a build target still ends up
+/// in the archive. Paths matched by `.dockerignore` are left out, file
+/// modes are kept and symlinks are added as links.
+pub(crate) fn pack_build_context(
+    context_dir: PathBuf,
+    dockerfile_name: String,
+    dockerfile: String,
+) -> Body {
+    let (sender, receiver) = channel(16);
+    tokio::task::spawn_blocking(move || {
+        let writer = ChannelWriter {
+            sender: sender.clone(),
+        };
+        let result = write_build_context(writer, &context_dir, &dockerfile_name, &dockerfile);
+        if let Err(e) = result {
+            sender.blocking_send(Err(e)).ok();
+        }
+    });
+    Body::wrap_stream(ReceiverStream::new(receiver))
+}
+
+fn write_build_context(
+    writer: ChannelWriter,
+    context_dir: &Path,
+    dockerfile_name: &str,
+    dockerfile: &str,
+) -> io::Result<()> {
+    let dockerignore = DockerIgnore::read(context_dir)?;
+    let encoder = flate2::write::GzEncoder::new(writer, flate2::Compression::default());
+    let mut tar = tar::Builder::new(encoder);
+    tar.follow_symlinks(false);
+
+    let mut header = tar::Header::new_gnu();
+    header.set_path(dockerfile_name)?;
+    header.set_size(dockerfile.len() as u64);
+    header.set_mode(0o644);
+    header.set_cksum();
+    tar.append(&header, dockerfile.as_bytes())?;
+
+    let skip = clean_path(dockerfile_name);
+    append_dir(&mut tar, context_dir, &mut vec![], &dockerignore, &skip)?;
+    tar.into_inner()?.finish()?.flush()
+}
+
+fn append_dir<W: Write>(
+    tar: &mut tar::Builder<W>,
+    dir: &Path,
+    relative: &mut Vec<String>,
+    dockerignore: &DockerIgnore,
+    skip: &[String],
+) -> io::Result<()> {
+    let mut entries = fs::read_dir(dir)?.collect::<io::Result<Vec<_>>>()?;
+    entries.sort_by_key(|entry| entry.file_name());
+    for entry in entries {
+        relative.push(entry.file_name().to_string_lossy().into_owned());
+        let path = entry.path();
+        let is_dir = entry.file_type()?.is_dir();
+        let excluded = relative.as_slice() == skip || dockerignore.is_excluded(relative);
+        if !excluded {
+            tar.append_path_with_name(&path, relative.join("/"))?;
+        }
+        if is_dir && (!excluded || dockerignore.may_include_below(relative)) {
+            append_dir(tar, &path, relative, dockerignore, skip)?;
+        }
+        relative.pop();
+    }
+    Ok(())
+}
+
+/// Hands whatever the tar and gzip encoders write to the request body.
+struct ChannelWriter {
+    sender: Sender<io::Result<Vec<u8>>>,
+}
+
+impl Write for ChannelWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.sender
+            .blocking_send(Ok(buf.to_vec()))
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "docker closed the build"))?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn excluded(dockerignore: &str, path: &str) -> bool {
+        DockerIgnore::parse(dockerignore).is_excluded(&clean_path(path))
+    }
+
+    #[test]
+    fn skips_comments_and_blank_lines() {
+        let dockerignore = DockerIgnore::parse("# logs\n\n  log  \n./tmp/\n");
+        assert_eq!(dockerignore.patterns.len(), 2);
+        assert!(dockerignore.is_excluded(&clean_path("tmp")));
+        assert!(!dockerignore.is_excluded(&clean_path("# logs")));
+    }
+
+    #[test]
+    fn excludes_paths_below_matched_directories() {
+        assert!(excluded("node_modules", "node_modules"));
+        assert!(excluded("node_modules", "node_modules/react/index.js"));
+        assert!(!excluded("node_modules", "app/node_modules"));
+        assert!(excluded("/tmp/../log", "log/development.log"));
+    }
+
+    #[test]
+    fn matches_globs_within_a_segment() {
+        assert!(excluded("*.log", "development.log"));
+        assert!(!excluded("*.log", "log/development.log"));
+        assert!(excluded("log/*.log", "log/development.log"));
+        assert!(excluded("file?.txt", "file1.txt"));
+        assert!(!excluded("file?.txt", "file10.txt"));
+        assert!(excluded("file[0-9].txt", "file5.txt"));
+        assert!(!excluded("file[^0-9].txt", "file5.txt"));
+        assert!(excluded("file[!0-9].txt", "filea.txt"));
+        assert!(excluded("\\*.txt", "*.txt"));
+        assert!(!excluded("\\*.txt", "a.txt"));
+    }
+
+    #[test]
+    fn matches_any_number_of_directories() {
+        assert!(excluded("**/*.log", "development.log"));
+        assert!(excluded("**/*.log", "log/rails/development.log"));
+        assert!(excluded("app/**/secrets", "app/secrets"));
+        assert!(excluded("app/**/secrets", "app/config/prod/secrets/key"));
+        assert!(!excluded("app/**/secrets", "lib/secrets"));
+    }
+
+    #[test]
+    fn negation_reincludes_paths() {
+        let dockerignore = "*.md\n!README.md";
+        assert!(excluded(dockerignore, "CHANGELOG.md"));
+        assert!(!excluded(dockerignore, "README.md"));
+        // the last matching pattern wins
+        assert!(excluded("!README.md\n*.md", "README.md"));
+        assert!(!excluded("log\n!log/keep\n", "log/keep"));
+        assert!(excluded("log\n!log/keep\n", "log/development.log"));
+    }
+
+    #[test]
+    fn walks_excluded_directories_a_negation_reaches_into() {
+        let dockerignore = DockerIgnore::parse("log\n!log/keep\nvendor\n!**/*.gemspec");
+        assert!(dockerignore.may_include_below(&clean_path("log")));
+        assert!(dockerignore.may_include_below(&clean_path("vendor/gems")));
+        assert!(!DockerIgnore::parse("log\n!tmp/keep").may_include_below(&clean_path("log")));
+    }
+}
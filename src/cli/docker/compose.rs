@@ -1,100 +1,156 @@
 use bollard::{
     container::{
-        AttachContainerResults, Config, CreateContainerOptions, ListContainersOptions,
-        NetworkingConfig,
+        AttachContainerResults, Config, CreateContainerOptions, LogsOptions, NetworkingConfig,
     },
     image::{BuildImageOptions, ListImagesOptions, RemoveImageOptions},
-    models::{ContainerState, EndpointSettings, HostConfig, Mount, MountTypeEnum, PortBinding},
+    models::{
+        ContainerState, ContainerSummary, EndpointSettings, HealthConfig, HealthStatusEnum,
+        HostConfig, Mount, PortBinding,
+    },
     network::{CreateNetworkOptions, ListNetworksOptions},
+    volume::CreateVolumeOptions,
 };
 use crossterm::style::Stylize;
 use docker_compose_types::{
-    Command, Compose, ComposeFile, ComposeVolumes, Environment, Services, TopLevelVolumes, Volumes,
+    Command, Compose, ComposeFile, DependsCondition, DependsOnOptions, Environment,
+    HealthcheckTest, Service, Services,
 };
-use futures_util::{StreamExt, TryStreamExt};
+use futures_util::TryStreamExt;
+use indexmap::IndexMap;
 use indicatif::ProgressBar;
 // use owo_colors::OwoColorize;
-use std::{collections::HashMap, env, error::Error, fs, io::Write};
+use std::{
+    collections::HashMap,
+    env,
+    error::Error,
+    fs,
+    path::{Component, Path, PathBuf},
+    sync::OnceLock,
+    time::{Duration, Instant},
+};
+use tokio::time::sleep;
 use tokio_stream::StreamMap;
+use uuid::Uuid;
 
 use super::{
-    get_container_id, get_containers, init_docker,
-    utils::{project_hash, run_container_command, ExecConfig},
+    build::{truncate_to_target, Build, BuildConfig},
+    build_context::pack_build_context,
+    dependencies::{start_order, with_dependencies},
+    extensions::{split_extensions, ComposeExtensions, Healthcheck, ServiceExtensions},
+    get_container_by_name, get_service_container, init_docker,
+    interpolation::{interpolate, project_variables, read_env_file},
+    labels::{
+        config_hash, oneoff_labels, project_filter, project_labels, service_labels,
+        CONFIG_HASH_LABEL, SERVICE_LABEL,
+    },
+    logs::{parse_timestamp, print_prefixed_output, LogsConfig, OutputStream},
+    merge::merge_compose_files,
+    ports::Port,
+    pull::{ensure_image, image_exists, pull_image, PullPolicy},
+    resolve_service_container, resolve_service_name, running_service_container,
+    utils::{
+        is_interactive, parse_duration, project_hash, run_attached, run_container_command,
+        ExecConfig,
+    },
+    volumes::{tmpfs_mounts, ProjectVolume, ServiceVolume, VolumeMount},
 };
 use crate::cli::{docker::utils::remove_container, traits::IntoArgs};
 
-pub async fn compose_up(detached: bool) -> Result<(), Box<dyn Error>> {
+/// When `compose_up` replaces a service's existing container with a new one.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Recreate {
+    /// only when the service config changed since the container was created
+    Changed,
+    Always,
+    Never,
+}
+
+/// Starts `service_names`, or every service when it's empty. Unless
+/// `no_deps` is set, the services they depend on are started as well.
+pub async fn compose_up(
+    service_names: Vec<String>,
+    no_deps: bool,
+    detached: bool,
+    wait_timeout: u64,
+    recreate: Recreate,
+) -> Result<(), Box<dyn Error>> {
     let mut started_containers = vec![];
     let path = env::current_dir()?;
     let project_hash = project_hash(path.to_str().unwrap());
     let project_name = path.file_name().unwrap().to_str().unwrap().to_owned();
     let docker = init_docker();
-    let Compose {
-        services, volumes, ..
-    } = parse_docker_compose_file()?;
-    let mut dc_volumes = vec![];
-    if let Some(TopLevelVolumes::CV(ComposeVolumes(volumes_maps))) = volumes {
-        for (volume_name, _volume_options) in volumes_maps {
-            dc_volumes.push(volume_name);
-        }
-    };
+    let Compose { services, .. } = parse_docker_compose_file()?;
+    let ComposeExtensions {
+        services: mut extensions,
+        volumes: project_volumes,
+    } = parse_compose_extensions()?;
+    let config_hashes = service_config_hashes()?;
+    create_volumes(&project_volumes).await?;
 
     let services = services.expect("Docker-compose file must have services");
-    let Services(services_map) = services;
-    let existing_networks = docker
-        .list_networks(None::<ListNetworksOptions<String>>)
-        .await?
-        .iter()
-        .map(|n| (n.name.clone().unwrap(), n.id.clone().unwrap()))
-        .collect::<HashMap<String, String>>();
+    let service_order = selected_services(&services, &service_names, !no_deps)?;
+    let Services(mut services_map) = services;
     let network_name = format!("{}_default", project_name);
-    let network_id = match existing_networks.get(network_name.as_str()) {
-        Some(id) => id.clone(),
-        None => docker
-            .create_network(CreateNetworkOptions {
-                name: network_name.clone(),
-                driver: "bridge".to_string(),
-                ..Default::default()
-            })
-            .await?
-            .id
-            .unwrap(),
+    let network_id = ensure_network(&project_name, &project_hash).await?;
+    let project = ProjectContext {
+        project_name: &project_name,
+        network_name: &network_name,
+        network_id: &network_id,
+        project_volumes: &project_volumes,
     };
+    let selected = service_order.clone();
 
-    for (service_name, service_config) in services_map {
-        let mut user = Some(format!("{}-user", &project_name));
-
-        let service_config = service_config.expect("Service must have config");
+    for service_name in service_order {
+        let service_config = services_map
+            .shift_remove(&service_name)
+            .flatten()
+            .expect("Service must have config");
+        let service_extensions = extensions.shift_remove(&service_name).unwrap_or_default();
 
         let container_name = format!("{}-{}-{}", project_name, &service_name, project_hash);
+        let config_hash = config_hashes
+            .get(&service_name)
+            .map(String::as_str)
+            .unwrap_or_default();
+        let existing_container =
+            match get_service_container(&docker, &project_hash, &service_name).await? {
+                Some(container) => Some(container),
+                // containers created before wizard labeled them are only
+                // known by their name. Having no config hash, they are
+                // recreated unless recreating is turned off
+                None => get_container_by_name(&docker, &container_name).await?,
+            };
+        let existing_container = match existing_container {
+            Some(container) if needs_recreate(&container, config_hash, recreate) => {
+                remove_outdated_container(&container_name, &container).await?;
+                None
+            }
+            container => container,
+        };
 
-        if container_exists(&container_name).await? {
-            if container_running(&container_name).await? {
+        if let Some(ContainerSummary {
+            state: Some(state), ..
+        }) = &existing_container
+        {
+            if state == "running" {
                 continue;
             }
+        }
+        wait_for_dependencies(
+            &service_name,
+            &service_config,
+            &project_name,
+            &project_hash,
+            no_deps.then_some(selected.as_slice()),
+            wait_timeout,
+        )
+        .await?;
+
+        if let Some(ContainerSummary { id: Some(id), .. }) = existing_container {
             let pb =
                 ProgressBar::new_spinner().with_message(format!("Starting {}", &container_name));
             pb.enable_steady_tick(100);
-            let network_name = format!("{}_default", project_name);
-
-            if !docker
-                .list_networks(None::<ListNetworksOptions<String>>)
-                .await?
-                .iter()
-                .map(|n| n.name.clone().unwrap())
-                .any(|x| x == network_name)
-            {
-                docker
-                    .create_network(CreateNetworkOptions {
-                        name: network_name,
-                        check_duplicate: true,
-                        ..Default::default()
-                    })
-                    .await?;
-            }
-            docker
-                .start_container::<String>(&container_name, None)
-                .await?;
+            docker.start_container::<String>(&id, None).await?;
             pb.finish_with_message(format!(
                 "{} {} [{}]",
                 "✔".green(),
@@ -103,64 +159,20 @@ pub async fn compose_up(detached: bool) -> Result<(), Box<dyn Error>> {
             ));
             continue;
         } else {
-            let mut endpoints_config = HashMap::new();
-            endpoints_config.insert(
-                network_name.clone(),
-                EndpointSettings {
-                    network_id: Some(network_id.clone()),
-                    aliases: Some(vec![
-                        format!("{}-{}", project_name, service_name),
-                        service_name.to_string(),
-                    ]),
-                    ..Default::default()
-                },
-            );
-            let env_file = match service_config.env_file {
-                Some(docker_compose_types::EnvFile::List(env_files_list)) => Some(env_files_list),
-                Some(docker_compose_types::EnvFile::Simple(env_file)) => Some(vec![env_file]),
-                None => None,
-            };
-            let networking_config = NetworkingConfig { endpoints_config };
-            let host_config = HostConfig {
-                mounts: extract_volumes(service_config.volumes, dc_volumes.clone()),
-                port_bindings: extract_ports(service_config.ports.clone()),
-                network_mode: Some(network_name.clone()),
-                ..Default::default()
-            };
-            let image_name = if service_config.image.is_some() {
-                user = None;
-                service_config.image.clone().unwrap()
-            // Otherwise create an image from dockerfile located in the project root
-            } else {
-                build_image_from_docker_file(service_name.clone()).await?;
-                service_name.clone()
-            };
-            let cmd = match &service_config.command {
-                Some(Command::Simple(cmd)) => cmd.try_into_args().ok(),
-                None => None,
-                _ => panic!("Unsupported command"),
-            };
-            let mut exposed_ports = HashMap::new();
-
-            if let Some(dc_ports) = service_config.ports.clone() {
-                for ports in dc_ports {
-                    let empty = HashMap::new();
-
-                    let port = format!("{}/tcp", ports.split(':').next().unwrap());
-                    exposed_ports.insert(port, empty.clone());
-                }
-            };
-            let container_config = Config {
-                user,
-                image: Some(image_name),
-                host_config: Some(host_config),
-                exposed_ports: Some(exposed_ports),
-                cmd,
-                networking_config: Some(networking_config),
-                env: extract_env((service_config.environment, env_file)),
-                ..Default::default()
-            };
-            let container_name = format!("{}-{}-{}", project_name, service_name, project_hash);
+            let mut container_config = service_container_config(
+                &project,
+                &service_name,
+                service_config,
+                service_extensions,
+                true,
+            )
+            .await?;
+            container_config.labels = Some(service_labels(
+                &project_name,
+                &project_hash,
+                &service_name,
+                config_hash,
+            ));
             let container_id = docker
                 .create_container(
                     Some(CreateContainerOptions {
@@ -216,104 +228,275 @@ pub async fn compose_up(detached: bool) -> Result<(), Box<dyn Error>> {
                 .await?;
             map.insert(container_name, output);
         }
-        let decorator_length = if started_containers.len() > 1 {
-            started_containers
-                .iter()
-                .cloned()
-                .map(|x| x.len())
-                .max()
-                .unwrap()
-        } else {
-            started_containers[0].len()
-        };
-        loop {
-            tokio::select! {
-                Some((ctnr, msg)) = map.next() => {
-                    let msg_bytes = msg?.into_bytes();
-                    let msg_string = String::from_utf8_lossy(&msg_bytes);
-                    let messages = msg_string.split('\n').map(|string|{
-                        let screen_width = crossterm::terminal::size().unwrap().0 as usize;
-
-                        if (string.len() + decorator_length) > screen_width {
-                            let test = string.split_at(screen_width - decorator_length - 6_usize);
-                            vec![test.0, test.1]
-                        } else {
-                            let test = string.split_at(string.len());
-                            vec![test.0]
-                        }
-                    }).flatten().collect::<Vec<&str>>();
-                    for message in messages {
-                        if !message.is_empty() {
-                            let container = format!("{:<width$}", ctnr.clone(), width = decorator_length);
-
-                            let container = if ctnr.len() < decorator_length {
-                                container.yellow()
-                            } else {
-                                container.cyan()
-                            };
-                            println!("{} | {}", container, message);
-                        }
-                    }
-                }
-                else => break,
-            }
-        }
+        print_prefixed_output(map, true).await?;
     }
     Ok(())
 }
 
-async fn container_exists(container_name: &str) -> Result<bool, Box<dyn Error>> {
+/// What the containers of a project share.
+struct ProjectContext<'a> {
+    project_name: &'a str,
+    network_name: &'a str,
+    network_id: &'a str,
+    project_volumes: &'a IndexMap<String, ProjectVolume>,
+}
+
+/// The id of the project's default network, created if it doesn't exist.
+async fn ensure_network(project_name: &str, project_hash: &str) -> Result<String, Box<dyn Error>> {
+    if let Some(id) = project_network_id(project_name, project_hash).await? {
+        return Ok(id);
+    }
     let docker = init_docker();
-    let options = Some(ListContainersOptions::<String> {
-        all: true,
+    let network = docker
+        .create_network(CreateNetworkOptions {
+            name: format!("{}_default", project_name),
+            driver: "bridge".to_string(),
+            labels: project_labels(project_name, project_hash),
+            ..Default::default()
+        })
+        .await?;
+    Ok(network.id.unwrap())
+}
+
+/// Turns a service's config into the settings its containers are created
+/// with, building or pulling the service's image first. Without `build`, a
+/// service's image that exists already isn't built again.
+async fn service_container_config(
+    project: &ProjectContext<'_>,
+    service_name: &str,
+    service_config: Service,
+    service_extensions: ServiceExtensions,
+    build: bool,
+) -> Result<Config<String>, Box<dyn Error>> {
+    let docker = init_docker();
+    let ProjectContext {
+        project_name,
+        network_name,
+        network_id,
+        project_volumes,
+        ..
+    } = *project;
+    let mut user = Some(format!("{}-user", project_name));
+    let mut endpoints_config = HashMap::new();
+    endpoints_config.insert(
+        network_name.to_string(),
+        EndpointSettings {
+            network_id: Some(network_id.to_string()),
+            aliases: Some(vec![
+                format!("{}-{}", project_name, service_name),
+                service_name.to_string(),
+            ]),
+            ..Default::default()
+        },
+    );
+    let env_file = match service_config.env_file {
+        Some(docker_compose_types::EnvFile::List(env_files_list)) => Some(env_files_list),
+        Some(docker_compose_types::EnvFile::Simple(env_file)) => Some(vec![env_file]),
+        None => None,
+    };
+    let networking_config = NetworkingConfig { endpoints_config };
+    let (port_bindings, exposed_ports) = extract_ports(service_name, service_extensions.ports)?;
+    let (mounts, binds) = extract_volumes(
+        service_name,
+        service_extensions.volumes,
+        project_name,
+        project_volumes,
+    )?;
+    let host_config = HostConfig {
+        mounts,
+        binds,
+        tmpfs: service_extensions.tmpfs.map(tmpfs_mounts),
+        port_bindings,
+        network_mode: Some(network_name.to_string()),
         ..Default::default()
-    });
-    let existing_containers = docker.list_containers(options).await?;
-    for container in existing_containers {
-        if container
-            .names
-            .clone()
-            .unwrap()
-            .contains(&format!("/{}", container_name))
-        {
-            return Ok(true);
+    };
+    let image_name = match (service_extensions.build, &service_config.image) {
+        (Some(build_config), image) => {
+            let image_name = image.clone().unwrap_or_else(|| service_name.to_string());
+            if build || !image_exists(&docker, &image_name).await {
+                build_image_from_docker_file(
+                    service_name,
+                    &image_name,
+                    build_config.into_config(),
+                    false,
+                )
+                .await?;
+            }
+            image_name
+        }
+        (None, Some(image)) => {
+            user = None;
+            ensure_image(&docker, service_name, image, service_extensions.pull_policy).await?;
+            image.clone()
+        }
+        // Otherwise create an image from dockerfile located in the project root
+        (None, None) => {
+            if build || !image_exists(&docker, service_name).await {
+                build_image_from_docker_file(
+                    service_name,
+                    service_name,
+                    BuildConfig::default(),
+                    false,
+                )
+                .await?;
+            }
+            service_name.to_string()
+        }
+    };
+    let cmd = extract_command(service_name, "command", service_config.command)?;
+    let entrypoint = extract_command(service_name, "entrypoint", service_extensions.entrypoint)?;
+    Ok(Config {
+        user,
+        image: Some(image_name),
+        host_config: Some(host_config),
+        exposed_ports: Some(exposed_ports),
+        cmd,
+        entrypoint,
+        networking_config: Some(networking_config),
+        env: extract_env((service_config.environment, env_file))?,
+        healthcheck: extract_healthcheck(service_extensions.healthcheck)?,
+        ..Default::default()
+    })
+}
+
+fn needs_recreate(container: &ContainerSummary, config_hash: &str, recreate: Recreate) -> bool {
+    match recreate {
+        Recreate::Always => true,
+        Recreate::Never => false,
+        Recreate::Changed => {
+            let created_with = container
+                .labels
+                .as_ref()
+                .and_then(|labels| labels.get(CONFIG_HASH_LABEL));
+            created_with.map(String::as_str) != Some(config_hash)
         }
     }
-    Ok(false)
 }
 
-async fn container_running(container_name: &str) -> Result<bool, Box<dyn Error>> {
+async fn remove_outdated_container(
+    container_name: &str,
+    container: &ContainerSummary,
+) -> Result<(), Box<dyn Error>> {
     let docker = init_docker();
-    let options = Some(ListContainersOptions::<String> {
-        all: true,
-        ..Default::default()
-    });
-    let existing_containers = docker.list_containers(options).await?;
-    for container in existing_containers {
-        if container
-            .names
-            .clone()
-            .unwrap()
-            .contains(&format!("/{}", container_name))
-        {
-            if let Some(status) = &container.state {
-                if status == "running" {
-                    return Ok(true);
+    let pb = ProgressBar::new_spinner().with_message(format!("Recreating {}", container_name));
+    pb.enable_steady_tick(100);
+    let id = container.id.as_deref().unwrap_or(container_name);
+    if container.state.as_deref() == Some("running") {
+        docker.stop_container(id, None).await?;
+    }
+    docker.remove_container(id, None).await?;
+    pb.finish_with_message(format!(
+        "{} {} [{}]",
+        "✔".green(),
+        container_name,
+        "removed".green()
+    ));
+    Ok(())
+}
+
+/// Blocks until the conditions `service_name` puts on its dependencies
+/// under `depends_on` are met, giving up after `wait_timeout` seconds.
+/// With `only`, dependencies outside of it aren't waited on.
+async fn wait_for_dependencies(
+    service_name: &str,
+    service_config: &Service,
+    project_name: &str,
+    project_hash: &str,
+    only: Option<&[String]>,
+    wait_timeout: u64,
+) -> Result<(), Box<dyn Error>> {
+    let conditions = match &service_config.depends_on {
+        Some(DependsOnOptions::Conditional(conditions)) => conditions,
+        _ => return Ok(()),
+    };
+    let docker = init_docker();
+    for (dependency, DependsCondition { condition }) in conditions {
+        if only.is_some_and(|only| !only.contains(dependency)) {
+            continue;
+        }
+        let wanted = match condition.as_str() {
+            "service_healthy" => "healthy",
+            "service_completed_successfully" => "completed",
+            _ => continue,
+        };
+        let container_name = format!("{}-{}-{}", project_name, dependency, project_hash);
+        let pb = ProgressBar::new_spinner()
+            .with_message(format!("Waiting for {} to be {}", &container_name, wanted));
+        pb.enable_steady_tick(100);
+        let started = Instant::now();
+        loop {
+            let state = docker
+                .inspect_container(&container_name, None)
+                .await?
+                .state
+                .unwrap_or_default();
+            let health = state.health.and_then(|health| health.status);
+            let failure = match (wanted, state.running, state.exit_code, health) {
+                ("healthy", _, _, Some(HealthStatusEnum::HEALTHY)) => break,
+                ("healthy", _, _, None) => Some("has no healthcheck".to_string()),
+                ("healthy", Some(false), _, _) => {
+                    Some("exited before becoming healthy".to_string())
+                }
+                ("completed", Some(false), Some(0), _) => break,
+                ("completed", Some(false), Some(code), _) => {
+                    Some(format!("exited with code {}", code))
+                }
+                _ if started.elapsed() > Duration::from_secs(wait_timeout) => {
+                    Some(format!("was not {} after {} seconds", wanted, wait_timeout))
                 }
+                _ => None,
+            };
+            if let Some(failure) = failure {
+                pb.abandon_with_message(format!(
+                    "{} {} [{}]",
+                    "✘".red(),
+                    &container_name,
+                    "failed".red()
+                ));
+                return Err(format!(
+                    "{} {}, but {} depends on it being {}",
+                    dependency, failure, service_name, wanted
+                )
+                .into());
             }
+            pb.set_message(format!(
+                "Waiting for {} to be {} ({}s)",
+                &container_name,
+                wanted,
+                started.elapsed().as_secs()
+            ));
+            sleep(Duration::from_millis(500)).await;
         }
+        pb.finish_with_message(format!(
+            "{} {} [{}]",
+            "✔".green(),
+            &container_name,
+            wanted.green()
+        ));
     }
-    Ok(false)
+    Ok(())
 }
 
-pub(crate) async fn compose_restart() {
+pub(crate) async fn compose_restart(service_names: Vec<String>) -> Result<(), Box<dyn Error>> {
+    let path = env::current_dir()?;
+    let project_hash = project_hash(path.to_str().unwrap());
     let docker = init_docker();
-    let containers = get_containers().await.unwrap();
+    let Compose { services, .. } = parse_docker_compose_file()?;
+    let services = services.expect("Docker-compose file must have services");
 
-    for container in containers {
+    for service_name in selected_services(&services, &service_names, false)? {
+        let (id, container) =
+            match get_service_container(&docker, &project_hash, &service_name).await? {
+                Some(ContainerSummary {
+                    id: Some(id),
+                    names: Some(names),
+                    ..
+                }) => (id, names.join(", ").trim_start_matches('/').to_string()),
+                _ => continue,
+            };
         let pb = ProgressBar::new_spinner().with_message(format!("Restarting {}", &container));
         pb.enable_steady_tick(100);
-        if docker.restart_container(&container, None).await.is_ok() {
+        if docker.restart_container(&id, None).await.is_ok() {
             pb.finish_with_message(format!(
                 "{} {} [{}]",
                 "✔".green(),
@@ -324,22 +507,39 @@ pub(crate) async fn compose_restart() {
             pb.abandon_with_message(format!("{} {} [{}]", "✘".red(), &container, "failed".red()));
         }
     }
+    Ok(())
 }
 
-pub(crate) async fn compose_build(force: bool) -> Result<(), Box<dyn std::error::Error>> {
-    compose_down().await?;
+pub(crate) async fn compose_build(
+    force: bool,
+    service_names: Vec<String>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    compose_down(service_names.clone()).await?;
     let Compose { services, .. } = parse_docker_compose_file()?;
+    let ComposeExtensions {
+        services: mut extensions,
+        ..
+    } = parse_compose_extensions()?;
     let services = services.expect("Docker-compose file must have services");
-    let Services(services_map) = services;
-    for (service_name, service_config) in services_map {
-        let service_config = service_config.expect("Service must have config");
-        if service_config.image.is_none() {
+    let service_order = selected_services(&services, &service_names, false)?;
+    let Services(mut services_map) = services;
+    for service_name in service_order {
+        let service_config = services_map
+            .shift_remove(&service_name)
+            .flatten()
+            .expect("Service must have config");
+        let build = extensions
+            .shift_remove(&service_name)
+            .and_then(|service_extensions| service_extensions.build);
+        if build.is_some() || service_config.image.is_none() {
+            let image_name = service_config.image.unwrap_or_else(|| service_name.clone());
+            let build = build.map(Build::into_config).unwrap_or_default();
             let pb = ProgressBar::new_spinner().with_message("Building containers");
             pb.enable_steady_tick(100);
             if force {
-                remove_image(&service_name).await?;
+                remove_image(&image_name).await?;
             }
-            if build_image_from_docker_file(service_name.to_string())
+            if build_image_from_docker_file(&service_name, &image_name, build, force)
                 .await
                 .is_ok()
             {
@@ -352,6 +552,230 @@ pub(crate) async fn compose_build(force: bool) -> Result<(), Box<dyn std::error:
     Ok(())
 }
 
+/// How `compose_run` creates its one-off container.
+#[derive(Debug, Default, Clone)]
+pub(crate) struct RunConfig {
+    /// replaces the service's command when not empty
+    pub(crate) command: Vec<String>,
+    /// `KEY=VALUE` overrides of the service's environment
+    pub(crate) env: Vec<String>,
+    pub(crate) service_ports: bool,
+    /// remove the container once the command exits
+    pub(crate) remove: bool,
+    /// build the service's image even if it exists already
+    pub(crate) build: bool,
+    /// run the command as this user instead of the service's
+    pub(crate) user: Option<String>,
+    /// run the command in this directory instead of the service's
+    pub(crate) work_dir: Option<String>,
+}
+
+/// Runs a command in a new container of `service_name`, the service's own
+/// command unless `config` gives one, and exits with the command's exit code.
+pub(crate) async fn compose_run(
+    service_name: String,
+    config: RunConfig,
+) -> Result<(), Box<dyn Error>> {
+    let RunConfig {
+        command,
+        env,
+        service_ports,
+        remove,
+        build,
+        user,
+        work_dir,
+    } = config;
+    let path = env::current_dir()?;
+    let project_hash = project_hash(path.to_str().unwrap());
+    let project_name = path.file_name().unwrap().to_str().unwrap().to_owned();
+    let docker = init_docker();
+    let Compose { services, .. } = parse_docker_compose_file()?;
+    let ComposeExtensions {
+        services: mut extensions,
+        volumes: project_volumes,
+    } = parse_compose_extensions()?;
+    let Services(mut services_map) = services.expect("Docker-compose file must have services");
+    let service_config = services_map
+        .shift_remove(&service_name)
+        .ok_or_else(|| format!("No such service: {}", service_name))?
+        .expect("Service must have config");
+    let service_extensions = extensions.shift_remove(&service_name).unwrap_or_default();
+    create_volumes(&project_volumes).await?;
+    let network_name = format!("{}_default", project_name);
+    let network_id = ensure_network(&project_name, &project_hash).await?;
+    let project = ProjectContext {
+        project_name: &project_name,
+        network_name: &network_name,
+        network_id: &network_id,
+        project_volumes: &project_volumes,
+    };
+
+    let mut container_config = service_container_config(
+        &project,
+        &service_name,
+        service_config,
+        service_extensions,
+        build,
+    )
+    .await?;
+    if !command.is_empty() {
+        container_config.cmd = Some(command);
+    }
+    container_config.env = Some(override_env(container_config.env.unwrap_or_default(), env));
+    if user.is_some() {
+        container_config.user = user;
+    }
+    if work_dir.is_some() {
+        container_config.working_dir = work_dir;
+    }
+    if !service_ports {
+        if let Some(host_config) = container_config.host_config.as_mut() {
+            host_config.port_bindings = None;
+        }
+    }
+    // one-off containers don't answer to the service's name on the network
+    if let Some(networking_config) = container_config.networking_config.as_mut() {
+        for endpoint in networking_config.endpoints_config.values_mut() {
+            endpoint.aliases = None;
+        }
+    }
+    let tty = is_interactive();
+    container_config.tty = Some(tty);
+    container_config.open_stdin = Some(true);
+    container_config.attach_stdin = Some(true);
+    container_config.attach_stdout = Some(true);
+    container_config.attach_stderr = Some(true);
+    container_config.labels = Some(oneoff_labels(&project_name, &project_hash, &service_name));
+
+    let container_name = format!(
+        "{}-{}-run-{}",
+        project_name,
+        service_name,
+        &Uuid::new_v4().to_simple().to_string()[..12]
+    );
+    let container_id = docker
+        .create_container(
+            Some(CreateContainerOptions {
+                name: &container_name,
+            }),
+            container_config,
+        )
+        .await?
+        .id;
+    let exit_code = run_attached(&docker, &container_id, tty).await;
+    if remove {
+        remove_container(&container_id).await;
+    }
+    match exit_code? {
+        0 => Ok(()),
+        code => std::process::exit(code as i32),
+    }
+}
+
+/// Adds `KEY=VALUE` overrides to a container's environment. A `KEY` without
+/// a value takes it from the environment wizard runs in.
+fn override_env(mut current: Vec<String>, overrides: Vec<String>) -> Vec<String> {
+    for variable in overrides {
+        let variable = match variable.split_once('=') {
+            Some(_) => variable,
+            None => match env::var(&variable) {
+                Ok(value) => format!("{}={}", variable, value),
+                Err(_) => continue,
+            },
+        };
+        let key = variable.split('=').next().unwrap_or_default().to_string();
+        current.retain(|existing| existing.split('=').next() != Some(key.as_str()));
+        current.push(variable);
+    }
+    current
+}
+
+/// Prints the logs of `service_names`, or of every service when it's empty.
+pub(crate) async fn compose_logs(
+    service_names: Vec<String>,
+    config: LogsConfig,
+) -> Result<(), Box<dyn Error>> {
+    let path = env::current_dir()?;
+    let project_hash = project_hash(path.to_str().unwrap());
+    let docker = init_docker();
+    let Compose { services, .. } = parse_docker_compose_file()?;
+    let services = services.expect("Docker-compose file must have services");
+    let options = LogsOptions {
+        follow: config.follow,
+        stdout: true,
+        stderr: true,
+        since: config
+            .since
+            .as_deref()
+            .map(parse_timestamp)
+            .transpose()?
+            .unwrap_or_default(),
+        until: config
+            .until
+            .as_deref()
+            .map(parse_timestamp)
+            .transpose()?
+            .unwrap_or_default(),
+        timestamps: config.timestamps,
+        tail: config.tail.unwrap_or_else(|| "all".to_string()),
+    };
+    let mut map: StreamMap<String, OutputStream> = StreamMap::new();
+    for service_name in selected_services(&services, &service_names, false)? {
+        if let Some(ContainerSummary {
+            id: Some(id),
+            names: Some(names),
+            ..
+        }) = get_service_container(&docker, &project_hash, &service_name).await?
+        {
+            let container_name = names.join(", ").trim_start_matches('/').to_string();
+            map.insert(
+                container_name,
+                Box::pin(docker.logs(&id, Some(options.clone()))),
+            );
+        }
+    }
+    if map.is_empty() {
+        println!(
+            "[{}]::Status - No containers to show logs for",
+            "Wizard".red()
+        );
+        return Ok(());
+    }
+    print_prefixed_output(map, config.color).await
+}
+
+/// Pulls the images of the services that use one without building it.
+/// Only the given services are pulled when `service_names` isn't empty.
+pub(crate) async fn compose_pull(service_names: Vec<String>) -> Result<(), Box<dyn Error>> {
+    let docker = init_docker();
+    let Compose { services, .. } = parse_docker_compose_file()?;
+    let ComposeExtensions {
+        services: mut extensions,
+        ..
+    } = parse_compose_extensions()?;
+    let services = services.expect("Docker-compose file must have services");
+    let service_order = selected_services(&services, &service_names, false)?;
+    let Services(mut services_map) = services;
+    for service_name in service_order {
+        let service_config = services_map.shift_remove(&service_name).flatten();
+        let service_extensions = extensions.shift_remove(&service_name).unwrap_or_default();
+        let image = match service_config.and_then(|config| config.image) {
+            Some(image) => image,
+            None => continue,
+        };
+        if service_extensions.build.is_some()
+            || matches!(
+                service_extensions.pull_policy,
+                PullPolicy::Never | PullPolicy::Build
+            )
+        {
+            continue;
+        }
+        pull_image(&docker, &image).await?;
+    }
+    Ok(())
+}
+
 async fn remove_image(image_name: &str) -> Result<(), Box<dyn Error>> {
     let docker = init_docker();
     let list_options = Some(ListImagesOptions::<String> {
@@ -385,24 +809,40 @@ async fn remove_image(image_name: &str) -> Result<(), Box<dyn Error>> {
     Ok(())
 }
 
-pub async fn compose_down() -> Result<(), Box<dyn Error>> {
+/// Stops and removes the containers of `service_names`, or of the whole
+/// project along with its network when it's empty.
+pub async fn compose_down(service_names: Vec<String>) -> Result<(), Box<dyn Error>> {
     let path = env::current_dir()?;
+    let project_hash = project_hash(path.to_str().unwrap());
     let project_name = path.file_name().unwrap().to_str().unwrap().to_owned();
-    let network_name = format!("{}_default", project_name);
     let docker = init_docker();
-    let containers = get_containers().await?;
-    for container in containers.iter() {
+    let Compose { services, .. } = parse_docker_compose_file()?;
+    let services = services.expect("Docker-compose file must have services");
+    // stop dependents before the services they depend on
+    for service_name in selected_services(&services, &service_names, false)?
+        .iter()
+        .rev()
+    {
+        let (id, container) =
+            match get_service_container(&docker, &project_hash, service_name).await? {
+                Some(ContainerSummary {
+                    id: Some(id),
+                    names: Some(names),
+                    ..
+                }) => (id, names.join(", ").trim_start_matches('/').to_string()),
+                _ => continue,
+            };
         let pb = ProgressBar::new_spinner().with_message(format!("Stopping {}", &container));
         pb.enable_steady_tick(100);
-        let inspect_container = docker.inspect_container(container, None).await?;
+        let inspect_container = docker.inspect_container(&id, None).await?;
         if let Some(ContainerState {
             running: Some(true),
             ..
         }) = inspect_container.state
         {
-            docker.stop_container(container, None).await?;
+            docker.stop_container(&id, None).await?;
         }
-        docker.remove_container(container, None).await?;
+        docker.remove_container(&id, None).await?;
         pb.finish_with_message(format!(
             "{} {} [{}]",
             "✔".green(),
@@ -411,166 +851,345 @@ pub async fn compose_down() -> Result<(), Box<dyn Error>> {
         ));
     }
 
-    if docker
-        .list_networks(None::<ListNetworksOptions<String>>)
-        .await?
+    if !service_names.is_empty() {
+        return Ok(());
+    }
+    if let Some(network_id) = project_network_id(&project_name, &project_hash).await? {
+        docker.remove_network(&network_id).await?;
+    }
+    Ok(())
+}
+
+/// The project's default network, if it was created already. Networks
+/// created before projects were labeled are found by their name.
+async fn project_network_id(
+    project_name: &str,
+    project_hash: &str,
+) -> Result<Option<String>, Box<dyn Error>> {
+    let docker = init_docker();
+    let networks = docker
+        .list_networks(Some(ListNetworksOptions {
+            filters: project_filter(project_hash),
+        }))
+        .await?;
+    if let Some(id) = networks.into_iter().find_map(|network| network.id) {
+        return Ok(Some(id));
+    }
+    let network_name = format!("{}_default", project_name);
+    let networks = docker
+        .list_networks(Some(ListNetworksOptions {
+            filters: HashMap::from([("name".to_string(), vec![network_name.clone()])]),
+        }))
+        .await?;
+    // the name filter matches substrings too
+    Ok(networks
+        .into_iter()
+        .find(|network| network.name.as_deref() == Some(network_name.as_str()))
+        .and_then(|network| network.id))
+}
+
+/// The services a command acts on, in start order: every service when
+/// `service_names` is empty, otherwise the named ones and, with
+/// `include_dependencies`, the services they depend on.
+fn selected_services(
+    services: &Services,
+    service_names: &[String],
+    include_dependencies: bool,
+) -> Result<Vec<String>, Box<dyn Error>> {
+    let Services(services_map) = services;
+    if let Some(unknown) = service_names
         .iter()
-        .map(|n| n.name.clone().unwrap())
-        .any(|x| x == network_name)
+        .find(|name| !services_map.contains_key(name.as_str()))
     {
-        docker.remove_network(&network_name).await?;
+        return Err(format!("No such service: {}", unknown).into());
     }
-    Ok(())
+    let order = start_order(services)?;
+    if service_names.is_empty() {
+        return Ok(order);
+    }
+    let selected = if include_dependencies {
+        with_dependencies(services, service_names)
+    } else {
+        service_names.to_vec()
+    };
+    Ok(order
+        .into_iter()
+        .filter(|service_name| selected.contains(service_name))
+        .collect())
 }
 
+/// The project's services, in the order they are started.
 pub(crate) fn get_service_names_from_compose_file() -> Result<Vec<String>, Box<dyn Error>> {
     let Compose { services, .. } = parse_docker_compose_file()?;
     let services = services.expect("Docker-compose file must have services");
-    let Services(services_map) = services;
+    start_order(&services)
+}
+
+type PortBindings = HashMap<String, Option<Vec<PortBinding>>>;
+type ExposedPorts = HashMap<String, HashMap<(), ()>>;
+type Mounts = Vec<Mount>;
+type Binds = Vec<String>;
+
+fn extract_ports(
+    service_name: &str,
+    dc_ports: Option<Vec<Port>>,
+) -> Result<(Option<PortBindings>, ExposedPorts), Box<dyn Error>> {
+    let mut exposed_ports = HashMap::new();
+    let ports_vec = match dc_ports {
+        Some(ports_vec) => ports_vec,
+        None => return Ok((None, exposed_ports)),
+    };
+    // several host ports may publish the same container port, so bindings
+    // are collected per container port (e.g. "3000/tcp")
+    let mut port_bindings: PortBindings = HashMap::new();
+    for port in ports_vec {
+        let mappings = port.mappings().map_err(|e| {
+            format!(
+                "Invalid port \"{}\" for service {}: {}",
+                port, service_name, e
+            )
+        })?;
+        for mapping in mappings {
+            let container_port = mapping.container_port();
+            exposed_ports.insert(container_port.clone(), HashMap::new());
+            port_bindings
+                .entry(container_port)
+                .or_insert_with(|| Some(vec![]))
+                .get_or_insert_with(Vec::new)
+                .push(PortBinding {
+                    host_ip: Some(mapping.host_ip.unwrap_or_else(|| String::from("0.0.0.0"))),
+                    host_port: Some(mapping.published.unwrap_or_default()),
+                });
+        }
+    }
+    Ok((Some(port_bindings), exposed_ports))
+}
+
+fn extract_volumes(
+    service_name: &str,
+    dc_service_volumes: Option<Vec<ServiceVolume>>,
+    project_name: &str,
+    project_volumes: &IndexMap<String, ProjectVolume>,
+) -> Result<(Option<Mounts>, Option<Binds>), Box<dyn Error>> {
+    let volumes_vec = match dc_service_volumes {
+        Some(volumes_vec) => volumes_vec,
+        None => return Ok((None, None)),
+    };
+    let mut mounts = vec![];
+    let mut binds = vec![];
+    for volume in volumes_vec {
+        let mount = volume
+            .to_mount(project_name, project_volumes)
+            .map_err(|e| {
+                format!(
+                    "Invalid volume \"{}\" for service {}: {}",
+                    volume, service_name, e
+                )
+            })?;
+        match mount {
+            VolumeMount::Mount(mount) => mounts.push(*mount),
+            VolumeMount::Bind(bind) => binds.push(bind),
+        }
+    }
+    Ok((Some(mounts), Some(binds).filter(|binds| !binds.is_empty())))
+}
+
+/// Creates the named volumes declared under the top-level `volumes` key
+/// that don't exist yet. External volumes must already exist.
+async fn create_volumes(
+    project_volumes: &IndexMap<String, ProjectVolume>,
+) -> Result<(), Box<dyn Error>> {
     let path = env::current_dir()?;
     let project_hash = project_hash(path.to_str().unwrap());
     let project_name = path.file_name().unwrap().to_str().unwrap().to_owned();
-    let mut service_names = vec![];
-    for (service_name, _) in services_map {
-        service_names.push(service_name);
+    let docker = init_docker();
+    for (volume_key, volume) in project_volumes {
+        let volume_name = volume.docker_name(&project_name, volume_key);
+        if docker.inspect_volume(&volume_name).await.is_ok() {
+            continue;
+        }
+        if volume.is_external() {
+            return Err(format!(
+                "External volume {} does not exist. Create it with \"docker volume create {}\"",
+                volume_name, volume_name
+            )
+            .into());
+        }
+        let pb = ProgressBar::new_spinner().with_message(format!("Creating {}", &volume_name));
+        pb.enable_steady_tick(100);
+        docker
+            .create_volume(CreateVolumeOptions {
+                name: volume_name.clone(),
+                driver: volume.driver.clone().unwrap_or_else(|| "local".to_string()),
+                driver_opts: volume
+                    .driver_opts
+                    .clone()
+                    .unwrap_or_default()
+                    .into_iter()
+                    .collect(),
+                labels: volume
+                    .labels
+                    .clone()
+                    .unwrap_or_default()
+                    .into_iter()
+                    .chain(project_labels(&project_name, &project_hash))
+                    .collect(),
+            })
+            .await?;
+        pb.finish_with_message(format!(
+            "{} {} [{}]",
+            "✔".green(),
+            &volume_name,
+            "created".green()
+        ));
     }
-    let service_names = service_names
-        .into_iter()
-        .map(|s| format!("{}-{}-{}", project_name, s, project_hash))
-        .collect();
-    Ok(service_names)
+    Ok(())
 }
 
-fn extract_ports(
-    dc_ports: Option<Vec<String>>,
-) -> Option<HashMap<String, Option<Vec<PortBinding>>>> {
-    if let Some(ports_vec) = dc_ports {
-        // ports are in an array of str (e.g. ["8080:8080"]) where [host:container]
-        // coming from docker-compose we need to create an Option<HashMap<T, HashMap<(), ()>>>
-        let mut port_bindings = HashMap::new();
-        for ports in ports_vec {
-            let ports = ports.split(':').collect::<Vec<_>>();
-            let host_port = ports[0];
-            let container_port = format!("{}/tcp", ports[1]);
-            port_bindings.insert(
-                container_port,
-                Some(vec![PortBinding {
-                    host_ip: Some(String::from("0.0.0.0")),
-                    host_port: Some(String::from(host_port)),
-                }]),
-            );
+/// Turns a `command` or `entrypoint` into the argument list docker expects.
+/// The string form is split the way a shell would split it.
+fn extract_command(
+    service_name: &str,
+    key: &str,
+    command: Option<Command>,
+) -> Result<Option<Vec<String>>, Box<dyn Error>> {
+    match command {
+        Some(Command::Simple(command)) => {
+            let args = command.try_into_args().map_err(|e| {
+                format!(
+                    "Invalid {} \"{}\" for service {}: {}",
+                    key, command, service_name, e
+                )
+            })?;
+            Ok(Some(args))
         }
-        Some(port_bindings)
-    } else {
-        None
+        Some(Command::Args(args)) => Ok(Some(args)),
+        None => Ok(None),
     }
 }
 
-fn extract_volumes(
-    dc_service_volumes: Option<Volumes>,
-    dc_volumes: Vec<String>,
-) -> Option<Vec<Mount>> {
-    let path = env::current_dir().unwrap().to_str().unwrap().to_string();
-    if let Some(Volumes::Simple(volumes_vec)) = dc_service_volumes {
-        let mut mounts = vec![];
-        for volumes in volumes_vec {
-            let volumes = volumes.split(':').collect::<Vec<_>>();
-            let host_path = if volumes[0] == "." {
-                path.as_str()
-            } else {
-                volumes[0]
-            };
-            let container_path = volumes[1];
-            let typ = if dc_volumes.contains(&host_path.to_string()) {
-                MountTypeEnum::VOLUME
-            } else {
-                MountTypeEnum::BIND
-            };
-            mounts.push(Mount {
-                target: Some(container_path.to_string()),
-                source: Some(host_path.to_string()),
-                typ: Some(typ),
-                consistency: Some(String::from("default")),
-                ..Default::default()
-            });
-        }
-        Some(mounts)
-    } else {
-        None
+fn extract_healthcheck(
+    healthcheck: Option<Healthcheck>,
+) -> Result<Option<HealthConfig>, Box<dyn Error>> {
+    let healthcheck = match healthcheck {
+        Some(healthcheck) => healthcheck,
+        None => return Ok(None),
+    };
+    if healthcheck.disable {
+        return Ok(Some(HealthConfig {
+            test: Some(vec!["NONE".to_string()]),
+            ..Default::default()
+        }));
     }
+    // a plain string runs through the container's shell, a list is passed as-is
+    let test = match healthcheck.test {
+        Some(HealthcheckTest::Single(test)) => Some(vec!["CMD-SHELL".to_string(), test]),
+        Some(HealthcheckTest::Multiple(test)) => Some(test),
+        None => None,
+    };
+    let nanoseconds = |duration: Option<String>| -> Result<Option<i64>, Box<dyn Error>> {
+        match duration {
+            Some(duration) => Ok(Some(parse_duration(&duration)?.as_nanos() as i64)),
+            None => Ok(None),
+        }
+    };
+    Ok(Some(HealthConfig {
+        test,
+        interval: nanoseconds(healthcheck.interval)?,
+        timeout: nanoseconds(healthcheck.timeout)?,
+        start_period: nanoseconds(healthcheck.start_period)?,
+        retries: healthcheck.retries,
+    }))
 }
 
-fn extract_env(dc_env: (Option<Environment>, Option<Vec<String>>)) -> Option<Vec<String>> {
+fn extract_env(
+    dc_env: (Option<Environment>, Option<Vec<String>>),
+) -> Result<Option<Vec<String>>, Box<dyn Error>> {
     let mut env = vec![];
-    if let Some(Environment::KvPair(map)) = dc_env.0 {
-        for (key, value) in map {
-            let value = match value {
-                Some(value) => value,
-                None => "".to_string(),
-            };
-            env.push(format!("{}={}", key, value));
+    match dc_env.0 {
+        Some(Environment::KvPair(map)) => {
+            for (key, value) in map {
+                let value = match value {
+                    Some(value) => value,
+                    None => "".to_string(),
+                };
+                env.push(format!("{}={}", key, value));
+            }
         }
+        Some(Environment::List(list)) => env.extend(list),
+        None => {}
     }
 
     if let Some(env_vec) = dc_env.1 {
         for env_var in env_vec {
-            let env_file_string = std::fs::read_to_string(&env_var)
-                .unwrap_or_else(|_| panic!("Could not read env file {}", &env_var));
-            for line in env_file_string.lines() {
-                let parts = line.split_once("=");
-                if let Some((key, value)) = parts {
-                    env.push(format!("{}={}", key, value));
-                }
+            for (key, value) in read_env_file(Path::new(&env_var))? {
+                env.push(format!("{}={}", key, value));
             }
         }
     }
 
     if !env.is_empty() {
-        Some(env)
+        Ok(Some(env))
     } else {
-        None
+        Ok(None)
     }
 }
 
-async fn build_image_from_docker_file(service_name: String) -> Result<(), Box<dyn Error>> {
+/// Builds `image_name` from the service's build context. Without a `build`
+/// section the project root and its Dockerfile are used.
+async fn build_image_from_docker_file(
+    service_name: &str,
+    image_name: &str,
+    build: BuildConfig,
+    no_cache: bool,
+) -> Result<(), Box<dyn Error>> {
     let docker = init_docker();
     let curr_dir = env::current_dir()?;
-    let dockerfile_path = curr_dir.join("Dockerfile");
-    let dockerfile = std::fs::read_to_string(&dockerfile_path).expect(
-        "Could not read Dockerfile. Make sure to run this command from the root of the project.",
-    );
-    let mut header = tar::Header::new_gnu();
-    header.set_path("Dockerfile").unwrap();
-    header.set_size(dockerfile.len() as u64);
-    header.set_mode(0o755);
-    header.set_cksum();
-    let current_dir = env::current_dir()?;
-    let context = fs::read_dir(current_dir)?;
-    let mut tar = tar::Builder::new(Vec::new());
-    tar.append(&header, dockerfile.as_bytes()).unwrap();
-    for entry in context {
-        let entry = entry?;
-        let path = entry.path();
-        let file_name = path.file_name().unwrap();
-        if file_name.to_str().unwrap() == "Dockerfile" {
-            continue;
-        }
-        tar.append_path(&file_name).unwrap();
-    }
-
-    let uncompressed = tar.into_inner().unwrap();
-    let mut c = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
-    c.write_all(&uncompressed).unwrap();
-    let compressed = c.finish().unwrap();
-
+    let project_hash = project_hash(curr_dir.to_str().unwrap());
+    let project_name = curr_dir.file_name().unwrap().to_str().unwrap().to_owned();
+    let context_dir = curr_dir.join(build.context.as_deref().unwrap_or("."));
+    let dockerfile_name = build.dockerfile.as_deref().unwrap_or("Dockerfile");
+    let dockerfile_path = context_dir.join(dockerfile_name);
+    let dockerfile = std::fs::read_to_string(&dockerfile_path).map_err(|e| {
+        format!(
+            "Could not read {}: {}. Make sure to run this command from the root of the project.",
+            dockerfile_path.display(),
+            e
+        )
+    })?;
+    let dockerfile = match &build.target {
+        Some(target) => truncate_to_target(&dockerfile, target)?,
+        None => dockerfile,
+    };
+    // the Dockerfile is sent at its place in the context, or at the root of
+    // the context when it lives outside of it
+    let dockerfile_name = if Path::new(dockerfile_name)
+        .components()
+        .all(|component| matches!(component, Component::Normal(_)))
+    {
+        dockerfile_name
+    } else {
+        "Dockerfile.wizard"
+    };
     let options = BuildImageOptions {
-        dockerfile: "Dockerfile",
-        t: &service_name,
+        dockerfile: dockerfile_name.to_string(),
+        t: image_name.to_string(),
         rm: true,
+        nocache: no_cache || build.no_cache,
+        buildargs: build.args.map(|args| args.to_map()).unwrap_or_default(),
+        labels: build
+            .labels
+            .map(|labels| labels.to_map())
+            .unwrap_or_default()
+            .into_iter()
+            .chain(project_labels(&project_name, &project_hash))
+            .chain([(SERVICE_LABEL.to_string(), service_name.to_string())])
+            .collect(),
         ..Default::default()
     };
-    let mut building = docker.build_image(options, None, Some(compressed.into()));
-    let pb = ProgressBar::new_spinner().with_message(format!("building {}", &service_name));
+    let context = pack_build_context(context_dir, dockerfile_name.to_string(), dockerfile);
+    let mut building = docker.build_image(options, None, Some(context));
+    let pb = ProgressBar::new_spinner().with_message(format!("building {}", image_name));
     pb.enable_steady_tick(100);
     while let Some(build_info) = building.try_next().await? {
         if let Some(stream) = build_info.stream.clone() {
@@ -590,21 +1209,84 @@ async fn build_image_from_docker_file(service_name: String) -> Result<(), Box<dy
     pb.finish_with_message(format!(
         "{} {} [{}]",
         "✔".green(),
-        &service_name,
+        image_name,
         "built".green()
     ));
     Ok(())
 }
 
-pub(crate) fn parse_docker_compose_file() -> Result<Compose, Box<dyn Error>> {
-    let docker_file = std::fs::read_to_string("docker-compose.yaml");
-    let docker_file = if docker_file.is_ok() {
-        docker_file
+/// Compose files passed with `-f/--file`, in the order they were given.
+static COMPOSE_FILES: OnceLock<Vec<PathBuf>> = OnceLock::new();
+
+/// File names looked up in the project root when no compose file is given.
+const DEFAULT_COMPOSE_FILES: &[&str] = &[
+    "compose.yaml",
+    "compose.yml",
+    "docker-compose.yaml",
+    "docker-compose.yml",
+];
+
+pub(crate) fn set_compose_files(files: Vec<PathBuf>) {
+    COMPOSE_FILES.set(files).ok();
+}
+
+/// The compose files making up the project, in merge order. Files given on
+/// the command line win over `COMPOSE_FILE`, which wins over the default
+/// file found in the project root and its `.override` file.
+fn compose_file_paths() -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    if let Some(files) = COMPOSE_FILES.get().filter(|files| !files.is_empty()) {
+        return Ok(files.clone());
+    }
+    if let Ok(compose_file) = env::var("COMPOSE_FILE") {
+        let separator = env::var("COMPOSE_PATH_SEPARATOR")
+            .unwrap_or_else(|_| if cfg!(windows) { ";" } else { ":" }.to_string());
+        let files = compose_file
+            .split(separator.as_str())
+            .filter(|file| !file.is_empty())
+            .map(PathBuf::from)
+            .collect::<Vec<_>>();
+        if !files.is_empty() {
+            return Ok(files);
+        }
+    }
+    let path = env::current_dir()?;
+    let base = DEFAULT_COMPOSE_FILES
+        .iter()
+        .map(|file| path.join(file))
+        .find(|file| file.is_file())
+        .ok_or("No compose.yaml or docker-compose.yml found in the current directory")?;
+    // docker-compose.yml is extended by docker-compose.override.yml
+    let override_file = base.with_extension(format!(
+        "override.{}",
+        base.extension().unwrap().to_str().unwrap()
+    ));
+    if override_file.is_file() {
+        Ok(vec![base, override_file])
     } else {
-        std::fs::read_to_string("docker-compose.yml")
-    };
-    let docker_file = docker_file?;
-    let dc: ComposeFile = serde_yaml::from_str(docker_file.as_str()).unwrap();
+        Ok(vec![base])
+    }
+}
+
+/// Reads, interpolates and merges the project's compose files. Relative
+/// paths in every file are resolved against the project root.
+fn read_docker_compose_file() -> Result<serde_yaml::Value, Box<dyn Error>> {
+    let variables = project_variables()?;
+    let mut merged = serde_yaml::Value::Null;
+    for compose_file in compose_file_paths()? {
+        let docker_file = fs::read_to_string(&compose_file)
+            .map_err(|e| format!("Could not read {}: {}", compose_file.display(), e))?;
+        let mut docker_file = serde_yaml::from_str(docker_file.as_str())
+            .map_err(|e| format!("Could not parse {}: {}", compose_file.display(), e))?;
+        interpolate(&mut docker_file, &variables)?;
+        merge_compose_files(&mut merged, docker_file);
+    }
+    Ok(merged)
+}
+
+pub(crate) fn parse_docker_compose_file() -> Result<Compose, Box<dyn Error>> {
+    let mut docker_file = read_docker_compose_file()?;
+    split_extensions(&mut docker_file)?;
+    let dc: ComposeFile = serde_yaml::from_value(docker_file)?;
     let docker_compose = match dc {
         ComposeFile::V2Plus(dc) => dc,
         _ => panic!("Unsupported docker-compose version. Please use v3 or higher"),
@@ -612,47 +1294,143 @@ pub(crate) fn parse_docker_compose_file() -> Result<Compose, Box<dyn Error>> {
     Ok(docker_compose)
 }
 
+/// Settings `parse_docker_compose_file` leaves out, see
+/// `extensions::split_extensions`.
+pub(crate) fn parse_compose_extensions() -> Result<ComposeExtensions, Box<dyn Error>> {
+    let mut docker_file = read_docker_compose_file()?;
+    split_extensions(&mut docker_file)
+}
+
+/// The `config_hash` of every service, stored on its container to tell
+/// whether the container still matches the compose files.
+pub(crate) fn service_config_hashes() -> Result<HashMap<String, String>, Box<dyn Error>> {
+    let docker_file = read_docker_compose_file()?;
+    let services = docker_file
+        .get("services")
+        .and_then(serde_yaml::Value::as_mapping)
+        .cloned()
+        .unwrap_or_default();
+    let mut hashes = HashMap::new();
+    for (service_name, service_config) in &services {
+        let service_name = match service_name.as_str() {
+            Some(service_name) => service_name.to_string(),
+            None => continue,
+        };
+        let env_file_variables = env_file_variables(service_config)?;
+        hashes.insert(
+            service_name,
+            config_hash(service_config, &env_file_variables),
+        );
+    }
+    Ok(hashes)
+}
+
+/// The variables a service reads from its `env_file`s, which change its
+/// container's environment without changing the compose file.
+fn env_file_variables(
+    service_config: &serde_yaml::Value,
+) -> Result<Vec<(String, String)>, Box<dyn Error>> {
+    let env_files = match service_config.get("env_file") {
+        Some(serde_yaml::Value::String(env_file)) => vec![env_file.as_str()],
+        Some(serde_yaml::Value::Sequence(env_files)) => env_files
+            .iter()
+            .filter_map(serde_yaml::Value::as_str)
+            .collect(),
+        _ => vec![],
+    };
+    let mut variables = vec![];
+    for env_file in env_files {
+        variables.extend(read_env_file(Path::new(env_file))?);
+    }
+    Ok(variables)
+}
+
+/// Runs a command in the running container of the project's main service,
+/// or in a one-off container of it when it isn't running.
+pub(crate) async fn compose_exec_or_run(config: ExecConfig<'_>) -> Result<(), Box<dyn Error>> {
+    let docker = init_docker();
+    let service_name = resolve_service_name(None)?;
+    if running_service_container(&docker, &service_name)
+        .await?
+        .is_some()
+    {
+        return compose_exec(Some(&service_name), config).await;
+    }
+    println!(
+        "[Wizard]::Status - {} is not running, using a one-off container",
+        service_name
+    );
+    let command = config
+        .command_args
+        .iter()
+        .map(|arg| arg.to_string())
+        .collect();
+    let env = config
+        .env
+        .unwrap_or_default()
+        .into_iter()
+        .map(String::from)
+        .collect();
+    let config = RunConfig {
+        command,
+        env,
+        remove: true,
+        user: config.user.map(String::from),
+        work_dir: config.work_dir.map(String::from),
+        ..Default::default()
+    };
+    compose_run(service_name, config).await
+}
+
+/// Runs a command in the running container of `service_name`, see
+/// `resolve_service_container` for the service used when it's `None`.
+pub(crate) async fn compose_exec(
+    service_name: Option<&str>,
+    config: ExecConfig<'_>,
+) -> Result<(), Box<dyn Error>> {
+    let docker = init_docker();
+    let id = resolve_service_container(&docker, service_name).await?;
+    match run_container_command(&docker, &id, config.clone()).await {
+        Ok(result) if result.detached => {
+            println!("\n[Wizard]::Status - detached, the command keeps running");
+            Ok(())
+        }
+        Ok(result) if result.success() => Ok(()),
+        // the command printed its own errors already
+        Ok(result) => std::process::exit(result.exit_code as i32),
+        Err(err) => {
+            println!(
+                "\n[{}] - failed to execute command: {}",
+                "error".dark_red(),
+                config.command_args.join(" ").cyan(),
+            );
+            println!(
+                "[{}] - Error from command: {}",
+                "error".dark_red(),
+                err.to_string().red(),
+            );
+            std::process::exit(1);
+        }
+    }
+}
+
 pub(crate) async fn enter_shell(
-    container_name: Option<String>,
+    service_name: Option<String>,
     user: Option<String>,
+    detach_keys: Option<String>,
 ) -> Result<(), Box<dyn Error>> {
-    let docker = init_docker();
     let path = env::current_dir()?;
-    let project_hash = project_hash(path.to_str().unwrap());
     let project_name = path.file_name().unwrap().to_str().unwrap().to_owned();
-    let user = if user.is_some() {
-        user.unwrap()
-    } else {
-        format!("{}-user", project_name)
+    let user = match user {
+        Some(user) => user,
+        None => format!("{}-user", project_name),
     };
-
-    let id = match container_name {
-        Some(container_name) => get_container_id(&docker, &container_name).await?,
-        None => format!("{}-{}-{}", project_name, project_name, project_hash),
-    };
-    let docker = init_docker();
     let enter_shell = ExecConfig {
         attach_stdin: Some(true),
         user: Some(&user),
         command_args: &["bash"],
+        detach_keys: detach_keys.as_deref(),
         ..Default::default()
     };
-
-    let run_result = run_container_command(&docker, &id, enter_shell.clone()).await;
-    if run_result.is_err() {
-        let err_message = run_result.unwrap_err().to_string();
-        remove_container(&id).await;
-        println!(
-            "\n[{}] - failed to execute command: {}",
-            "error".dark_red(),
-            enter_shell.command_args.join(" ").cyan(),
-        );
-        println!(
-            "[{}] - Error from command: {}",
-            "error".dark_red(),
-            err_message.clone().red(),
-        );
-        std::process::exit(1);
-    }
-    Ok(())
+    compose_exec(service_name.as_deref(), enter_shell).await
 }
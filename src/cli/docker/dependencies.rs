@@ -0,0 +1,173 @@
+use docker_compose_types::{DependsOnOptions, Service, Services};
+use std::error::Error;
+
+/// Names of the services `service` lists under `depends_on`, in file order.
+pub(crate) fn service_dependencies(service: &Service) -> Vec<String> {
+    match &service.depends_on {
+        Some(DependsOnOptions::Simple(services)) => services.clone(),
+        Some(DependsOnOptions::Conditional(services)) => services.keys().cloned().collect(),
+        None => vec![],
+    }
+}
+
+/// Orders the services so that every service comes after the services it
+/// depends on. Services with no ordering constraint between them keep the
+/// order they have in the docker-compose file.
+pub(crate) fn start_order(services: &Services) -> Result<Vec<String>, Box<dyn Error>> {
+    let Services(services_map) = services;
+    let mut pending = vec![];
+    for (service_name, service_config) in services_map {
+        let dependencies = service_config
+            .as_ref()
+            .map(service_dependencies)
+            .unwrap_or_default();
+        for dependency in &dependencies {
+            if !services_map.contains_key(dependency) {
+                return Err(format!(
+                    "Service {} depends on undefined service {}",
+                    service_name, dependency
+                )
+                .into());
+            }
+        }
+        pending.push((service_name.clone(), dependencies));
+    }
+
+    let mut order: Vec<String> = vec![];
+    while !pending.is_empty() {
+        let ready = pending
+            .iter()
+            .position(|(_, dependencies)| dependencies.iter().all(|d| order.contains(d)));
+        match ready {
+            Some(index) => order.push(pending.remove(index).0),
+            None => {
+                let cycle = find_cycle(&pending);
+                return Err(format!(
+                    "Circular dependency between services: {}",
+                    cycle.join(" -> ")
+                )
+                .into());
+            }
+        }
+    }
+    Ok(order)
+}
+
+/// Walks the dependencies of services that could not be ordered until a
+/// service is visited twice, returning the path that closes the loop.
+fn find_cycle(pending: &[(String, Vec<String>)]) -> Vec<String> {
+    let mut path: Vec<String> = vec![];
+    let mut current = pending[0].0.clone();
+    loop {
+        if let Some(start) = path.iter().position(|name| name == &current) {
+            let mut cycle = path.split_off(start);
+            cycle.push(current);
+            return cycle;
+        }
+        path.push(current.clone());
+        // every pending service has at least one dependency that is also pending
+        current = pending
+            .iter()
+            .find(|(name, _)| name == &current)
+            .and_then(|(_, dependencies)| {
+                dependencies
+                    .iter()
+                    .find(|d| pending.iter().any(|(name, _)| &name == d))
+            })
+            .cloned()
+            .unwrap();
+    }
+}
+
+/// The services `service_names` depend on, directly or through other
+/// services, along with the services themselves.
+pub(crate) fn with_dependencies(services: &Services, service_names: &[String]) -> Vec<String> {
+    let Services(services_map) = services;
+    let mut selected = vec![];
+    let mut pending = service_names.to_vec();
+    while let Some(service_name) = pending.pop() {
+        if selected.contains(&service_name) {
+            continue;
+        }
+        if let Some(Some(service_config)) = services_map.get(&service_name) {
+            pending.extend(service_dependencies(service_config));
+        }
+        selected.push(service_name);
+    }
+    selected
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn services(yaml: &str) -> Services {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    fn names(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn keeps_file_order_of_independent_services() {
+        let services = services("web: {}\ndb: {}\ncache: {}");
+        assert_eq!(
+            start_order(&services).unwrap(),
+            names(&["web", "db", "cache"])
+        );
+    }
+
+    #[test]
+    fn starts_dependencies_first() {
+        let services = services(
+            "web:\n  depends_on: [db, cache]\ncache: {}\ndb:\n  depends_on:\n    cache:\n      condition: service_started",
+        );
+        assert_eq!(
+            start_order(&services).unwrap(),
+            names(&["cache", "db", "web"])
+        );
+    }
+
+    #[test]
+    fn rejects_undefined_dependencies() {
+        let services = services("web:\n  depends_on: [db]");
+        let err = start_order(&services).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Service web depends on undefined service db"
+        );
+    }
+
+    #[test]
+    fn rejects_self_dependencies() {
+        let services = services("web:\n  depends_on: [web]");
+        let err = start_order(&services).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Circular dependency between services: web -> web"
+        );
+    }
+
+    #[test]
+    fn rejects_circular_dependencies() {
+        let services = services(
+            "cache: {}\na:\n  depends_on: [b]\nb:\n  depends_on: [a, cache]\nweb:\n  depends_on: [a]",
+        );
+        let err = start_order(&services).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Circular dependency between services: a -> b -> a"
+        );
+    }
+
+    #[test]
+    fn selects_transitive_dependencies() {
+        let services = services(
+            "web:\n  depends_on: [api]\napi:\n  depends_on: [db]\ndb: {}\nworker:\n  depends_on: [db]",
+        );
+        let mut selected = with_dependencies(&services, &names(&["web"]));
+        selected.sort();
+        assert_eq!(selected, names(&["api", "db", "web"]));
+    }
+}
@@ -0,0 +1,97 @@
+use super::{
+    build::Build,
+    ports::Port,
+    pull::PullPolicy,
+    volumes::{ProjectVolume, ServiceVolume, Tmpfs},
+};
+use docker_compose_types::{Command, HealthcheckTest};
+use indexmap::IndexMap;
+use serde::Deserialize;
+use serde_yaml::Value;
+use std::error::Error;
+
+/// Service keys wizard reads itself instead of going through
+/// `docker_compose_types`, which either has no field for them or rejects
+/// part of the syntax the compose spec allows.
+const SERVICE_KEYS: &[&str] = &[
+    "healthcheck",
+    "ports",
+    "volumes",
+    "tmpfs",
+    "entrypoint",
+    "build",
+    "pull_policy",
+];
+
+#[derive(Debug, Default, Clone, Deserialize)]
+pub(crate) struct ServiceExtensions {
+    pub(crate) healthcheck: Option<Healthcheck>,
+    pub(crate) ports: Option<Vec<Port>>,
+    pub(crate) volumes: Option<Vec<ServiceVolume>>,
+    pub(crate) tmpfs: Option<Tmpfs>,
+    pub(crate) entrypoint: Option<Command>,
+    pub(crate) build: Option<Build>,
+    #[serde(default)]
+    pub(crate) pull_policy: PullPolicy,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub(crate) struct Healthcheck {
+    pub(crate) test: Option<HealthcheckTest>,
+    pub(crate) interval: Option<String>,
+    pub(crate) timeout: Option<String>,
+    pub(crate) start_period: Option<String>,
+    pub(crate) retries: Option<i64>,
+    #[serde(default)]
+    pub(crate) disable: bool,
+}
+
+/// Everything `split_extensions` lifts out of a compose file.
+#[derive(Debug, Default, Clone)]
+pub(crate) struct ComposeExtensions {
+    pub(crate) services: IndexMap<String, ServiceExtensions>,
+    /// The top-level `volumes`, which `docker_compose_types` can only read
+    /// when every option is a plain string.
+    pub(crate) volumes: IndexMap<String, ProjectVolume>,
+}
+
+/// Removes the keys listed in `SERVICE_KEYS` from every service of the raw
+/// compose file, along with the top-level `volumes`, and returns them parsed.
+pub(crate) fn split_extensions(compose: &mut Value) -> Result<ComposeExtensions, Box<dyn Error>> {
+    let mut extensions = ComposeExtensions::default();
+    let compose = match compose.as_mapping_mut() {
+        Some(compose) => compose,
+        None => return Ok(extensions),
+    };
+    if let Some(volumes) = compose.remove(&Value::from("volumes")) {
+        let volumes: IndexMap<String, Option<ProjectVolume>> = serde_yaml::from_value(volumes)
+            .map_err(|e| format!("Invalid top-level volumes: {}", e))?;
+        extensions.volumes = volumes
+            .into_iter()
+            .map(|(name, volume)| (name, volume.unwrap_or_default()))
+            .collect();
+    }
+    let services = match compose
+        .get_mut(&Value::from("services"))
+        .and_then(Value::as_mapping_mut)
+    {
+        Some(services) => services,
+        None => return Ok(extensions),
+    };
+    for (service_name, service_config) in services.iter_mut() {
+        let service_name = service_name.as_str().unwrap_or_default().to_string();
+        let mut lifted = serde_yaml::Mapping::new();
+        if let Some(service_config) = service_config.as_mapping_mut() {
+            for key in SERVICE_KEYS {
+                let key = Value::from(*key);
+                if let Some(value) = service_config.remove(&key) {
+                    lifted.insert(key, value);
+                }
+            }
+        }
+        let service_extensions = serde_yaml::from_value(Value::Mapping(lifted))
+            .map_err(|e| format!("Invalid configuration for service {}: {}", service_name, e))?;
+        extensions.services.insert(service_name, service_extensions);
+    }
+    Ok(extensions)
+}
@@ -0,0 +1,257 @@
+use serde_yaml::Value;
+use std::{collections::HashMap, env, error::Error, fs, path::Path};
+
+/// The variables a compose file can reference: the project's `.env` file,
+/// overridden by the environment wizard runs in.
+pub(crate) fn project_variables() -> Result<HashMap<String, String>, Box<dyn Error>> {
+    let env_file = env::current_dir()?.join(".env");
+    let mut variables: HashMap<String, String> = if env_file.is_file() {
+        read_env_file(&env_file)?.into_iter().collect()
+    } else {
+        HashMap::new()
+    };
+    variables.extend(env::vars());
+    Ok(variables)
+}
+
+/// Reads `KEY=VALUE` lines, skipping blank lines and `#` comments. Values
+/// may be wrapped in single or double quotes.
+pub(crate) fn read_env_file(path: &Path) -> Result<Vec<(String, String)>, Box<dyn Error>> {
+    let contents = fs::read_to_string(path)
+        .map_err(|e| format!("Could not read env file {}: {}", path.display(), e))?;
+    let mut variables = vec![];
+    for line in contents.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line = line.strip_prefix("export ").unwrap_or(line);
+        let (key, value) = match line.split_once('=') {
+            Some((key, value)) => (key.trim(), value.trim()),
+            None => continue,
+        };
+        let value = match value.chars().next() {
+            Some(quote @ ('"' | '\'')) if value.len() > 1 && value.ends_with(quote) => {
+                &value[1..value.len() - 1]
+            }
+            // unquoted values end at an inline comment
+            _ => value.split(" #").next().unwrap_or_default().trim_end(),
+        };
+        variables.push((key.to_string(), value.to_string()));
+    }
+    Ok(variables)
+}
+
+/// Substitutes `$VAR`, `${VAR}` and the `${VAR:-default}`, `${VAR-default}`,
+/// `${VAR:?error}`, `${VAR?error}`, `${VAR:+replacement}` and
+/// `${VAR+replacement}` forms in every string value of the compose file.
+/// `$$` is an escaped `$`.
+pub(crate) fn interpolate(
+    value: &mut Value,
+    variables: &HashMap<String, String>,
+) -> Result<(), Box<dyn Error>> {
+    match value {
+        Value::String(string) => *string = interpolate_str(string, variables)?,
+        Value::Sequence(sequence) => {
+            for item in sequence {
+                interpolate(item, variables)?;
+            }
+        }
+        Value::Mapping(mapping) => {
+            for (_, item) in mapping.iter_mut() {
+                interpolate(item, variables)?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+fn interpolate_str(
+    input: &str,
+    variables: &HashMap<String, String>,
+) -> Result<String, Box<dyn Error>> {
+    let mut output = String::with_capacity(input.len());
+    let mut rest = input;
+    while let Some(index) = rest.find('$') {
+        output.push_str(&rest[..index]);
+        rest = &rest[index + 1..];
+        if let Some(tail) = rest.strip_prefix('$') {
+            output.push('$');
+            rest = tail;
+        } else if let Some(tail) = rest.strip_prefix('{') {
+            let end = closing_brace(tail)
+                .ok_or_else(|| format!("Invalid interpolation format for \"{}\"", input))?;
+            output.push_str(&substitute(&tail[..end], variables)?);
+            rest = &tail[end + 1..];
+        } else {
+            let length = variable_name_length(rest);
+            if length == 0 {
+                output.push('$');
+            } else {
+                let name = &rest[..length];
+                output.push_str(variables.get(name).map(String::as_str).unwrap_or_default());
+            }
+            rest = &rest[length..];
+        }
+    }
+    output.push_str(rest);
+    Ok(output)
+}
+
+/// Resolves the contents of a `${...}` expression.
+fn substitute(
+    expression: &str,
+    variables: &HashMap<String, String>,
+) -> Result<String, Box<dyn Error>> {
+    let length = variable_name_length(expression);
+    let (name, operator) = expression.split_at(length);
+    if name.is_empty() {
+        return Err(format!("Invalid interpolation format for \"${{{}}}\"", expression).into());
+    }
+    let value = variables.get(name);
+    let set_and_not_empty = value.map(|value| !value.is_empty()).unwrap_or(false);
+    let (condition_met, argument, kind) = match operator {
+        "" => return Ok(value.cloned().unwrap_or_default()),
+        _ if operator.starts_with(":-") => (set_and_not_empty, &operator[2..], '-'),
+        _ if operator.starts_with(":?") => (set_and_not_empty, &operator[2..], '?'),
+        _ if operator.starts_with(":+") => (set_and_not_empty, &operator[2..], '+'),
+        _ if operator.starts_with('-') => (value.is_some(), &operator[1..], '-'),
+        _ if operator.starts_with('?') => (value.is_some(), &operator[1..], '?'),
+        _ if operator.starts_with('+') => (value.is_some(), &operator[1..], '+'),
+        _ => {
+            return Err(format!("Invalid interpolation format for \"${{{}}}\"", expression).into())
+        }
+    };
+    match (kind, condition_met) {
+        ('-', true) | ('?', true) => Ok(value.cloned().unwrap_or_default()),
+        ('-', false) | ('+', true) => interpolate_str(argument, variables),
+        ('?', false) => Err(format!(
+            "Required variable {} is missing a value: {}",
+            name,
+            interpolate_str(argument, variables)?
+        )
+        .into()),
+        _ => Ok(String::new()),
+    }
+}
+
+/// Finds the `}` closing an expression, skipping nested `${...}` defaults.
+fn closing_brace(expression: &str) -> Option<usize> {
+    let mut depth = 0;
+    for (index, c) in expression.char_indices() {
+        match c {
+            '{' => depth += 1,
+            '}' if depth == 0 => return Some(index),
+            '}' => depth -= 1,
+            _ => {}
+        }
+    }
+    None
+}
+
+fn variable_name_length(input: &str) -> usize {
+    input
+        .char_indices()
+        .take_while(|(index, c)| {
+            c == &'_' || c.is_ascii_alphabetic() || (*index > 0 && c.is_ascii_digit())
+        })
+        .count()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn variables() -> HashMap<String, String> {
+        HashMap::from([
+            ("TAG".to_string(), "1.2".to_string()),
+            ("EMPTY".to_string(), String::new()),
+            ("PORT_1".to_string(), "3000".to_string()),
+        ])
+    }
+
+    fn interpolated(input: &str) -> String {
+        interpolate_str(input, &variables()).unwrap()
+    }
+
+    #[test]
+    fn substitutes_plain_variables() {
+        assert_eq!(interpolated("ruby:$TAG"), "ruby:1.2");
+        assert_eq!(interpolated("ruby:${TAG}-slim"), "ruby:1.2-slim");
+        assert_eq!(interpolated("$PORT_1:3000"), "3000:3000");
+        assert_eq!(interpolated("${MISSING}"), "");
+        assert_eq!(interpolated("costs 5$"), "costs 5$");
+    }
+
+    #[test]
+    fn unescapes_double_dollars() {
+        assert_eq!(interpolated("$$TAG"), "$TAG");
+        assert_eq!(interpolated("echo $${TAG} $TAG"), "echo ${TAG} 1.2");
+    }
+
+    #[test]
+    fn applies_defaults() {
+        assert_eq!(interpolated("${MISSING:-latest}"), "latest");
+        assert_eq!(interpolated("${EMPTY:-latest}"), "latest");
+        assert_eq!(interpolated("${EMPTY-latest}"), "");
+        assert_eq!(interpolated("${MISSING-latest}"), "latest");
+        assert_eq!(interpolated("${TAG:-latest}"), "1.2");
+        assert_eq!(interpolated("${MISSING:-${TAG}}"), "1.2");
+    }
+
+    #[test]
+    fn applies_replacements() {
+        assert_eq!(interpolated("${TAG:+set}"), "set");
+        assert_eq!(interpolated("${EMPTY:+set}"), "");
+        assert_eq!(interpolated("${EMPTY+set}"), "set");
+        assert_eq!(interpolated("${MISSING+set}"), "");
+    }
+
+    #[test]
+    fn requires_variables() {
+        assert_eq!(interpolated("${TAG:?tag is needed}"), "1.2");
+        assert_eq!(interpolated("${EMPTY?tag is needed}"), "");
+        let error = interpolate_str("${MISSING?tag is needed}", &variables()).unwrap_err();
+        assert!(error.to_string().contains("tag is needed"));
+        assert!(interpolate_str("${EMPTY:?tag is needed}", &variables()).is_err());
+    }
+
+    #[test]
+    fn rejects_invalid_expressions() {
+        assert!(interpolate_str("${TAG", &variables()).is_err());
+        assert!(interpolate_str("${}", &variables()).is_err());
+        assert!(interpolate_str("${TAG!x}", &variables()).is_err());
+    }
+
+    #[test]
+    fn interpolates_nested_values() {
+        let mut value: Value =
+            serde_yaml::from_str("image: ruby:${TAG}\nports: [\"${PORT_1}:3000\"]").unwrap();
+        interpolate(&mut value, &variables()).unwrap();
+        let expected: Value =
+            serde_yaml::from_str("image: ruby:1.2\nports: [\"3000:3000\"]").unwrap();
+        assert_eq!(value, expected);
+    }
+
+    #[test]
+    fn reads_env_files() {
+        let path = env::temp_dir().join(format!("wizard-env-{}", std::process::id()));
+        fs::write(
+            &path,
+            "# comment\n\nexport TAG=1.2\nQUOTED=\"a # b\"\nSINGLE='x'\nINLINE=value # note\nNOT A VARIABLE\n",
+        )
+        .unwrap();
+        let variables = read_env_file(&path);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(
+            variables.unwrap(),
+            vec![
+                ("TAG".to_string(), "1.2".to_string()),
+                ("QUOTED".to_string(), "a # b".to_string()),
+                ("SINGLE".to_string(), "x".to_string()),
+                ("INLINE".to_string(), "value".to_string()),
+            ]
+        );
+    }
+}
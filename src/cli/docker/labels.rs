@@ -0,0 +1,84 @@
+use serde_yaml::Value;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+
+/// Labels wizard puts on every container, network, volume and image it
+/// creates, so the resources of a project can be found without relying on
+/// their names.
+pub(crate) const PROJECT_LABEL: &str = "wizard.project";
+pub(crate) const PROJECT_HASH_LABEL: &str = "wizard.project_hash";
+pub(crate) const SERVICE_LABEL: &str = "wizard.service";
+pub(crate) const CONFIG_HASH_LABEL: &str = "wizard.config_hash";
+/// Put on `wizard run` containers instead of the service label, so they are
+/// never mistaken for the service's own container.
+pub(crate) const ONEOFF_LABEL: &str = "wizard.oneoff";
+
+pub(crate) fn project_labels(project_name: &str, project_hash: &str) -> HashMap<String, String> {
+    HashMap::from([
+        (PROJECT_LABEL.to_string(), project_name.to_string()),
+        (PROJECT_HASH_LABEL.to_string(), project_hash.to_string()),
+    ])
+}
+
+pub(crate) fn service_labels(
+    project_name: &str,
+    project_hash: &str,
+    service_name: &str,
+    config_hash: &str,
+) -> HashMap<String, String> {
+    let mut labels = project_labels(project_name, project_hash);
+    labels.insert(SERVICE_LABEL.to_string(), service_name.to_string());
+    labels.insert(CONFIG_HASH_LABEL.to_string(), config_hash.to_string());
+    labels
+}
+
+pub(crate) fn oneoff_labels(
+    project_name: &str,
+    project_hash: &str,
+    service_name: &str,
+) -> HashMap<String, String> {
+    let mut labels = project_labels(project_name, project_hash);
+    labels.insert(ONEOFF_LABEL.to_string(), service_name.to_string());
+    labels
+}
+
+/// List filters matching the resources of the project. The project hash
+/// is derived from the project path, so projects sharing a name don't match.
+pub(crate) fn project_filter(project_hash: &str) -> HashMap<String, Vec<String>> {
+    HashMap::from([(
+        "label".to_string(),
+        vec![format!("{}={}", PROJECT_HASH_LABEL, project_hash)],
+    )])
+}
+
+pub(crate) fn service_filter(
+    project_hash: &str,
+    service_name: &str,
+) -> HashMap<String, Vec<String>> {
+    HashMap::from([(
+        "label".to_string(),
+        vec![
+            format!("{}={}", PROJECT_HASH_LABEL, project_hash),
+            format!("{}={}", SERVICE_LABEL, service_name),
+        ],
+    )])
+}
+
+/// Hashes a service's configuration as it was read from the compose files,
+/// after interpolation and merging, along with the variables it reads from
+/// its env files.
+pub(crate) fn config_hash(
+    service_config: &Value,
+    env_file_variables: &[(String, String)],
+) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(serde_yaml::to_string(service_config).unwrap_or_default());
+    for (key, value) in env_file_variables {
+        hasher.update(format!("{}={}\n", key, value));
+    }
+    hasher
+        .finalize()
+        .into_iter()
+        .map(|c| format!("{:02x}", c))
+        .collect()
+}
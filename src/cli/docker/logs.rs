@@ -0,0 +1,88 @@
+use bollard::container::LogOutput;
+use chrono::{DateTime, Utc};
+use crossterm::style::Stylize;
+use futures_util::Stream;
+use std::{error::Error, pin::Pin};
+use tokio_stream::{StreamExt, StreamMap};
+
+use super::utils::parse_duration;
+
+/// What `compose_logs` asks docker for.
+#[derive(Debug, Default, Clone)]
+pub(crate) struct LogsConfig {
+    pub(crate) follow: bool,
+    /// number of lines to show from the end of the logs, or `all`
+    pub(crate) tail: Option<String>,
+    pub(crate) since: Option<String>,
+    pub(crate) until: Option<String>,
+    pub(crate) timestamps: bool,
+    pub(crate) color: bool,
+}
+
+pub(crate) type OutputStream =
+    Pin<Box<dyn Stream<Item = Result<LogOutput, bollard::errors::Error>> + Send>>;
+
+/// Prints the output of several containers as it arrives, every line
+/// prefixed with the name of the container it came from. The names are
+/// padded to the longest one so the output lines up.
+pub(crate) async fn print_prefixed_output(
+    mut outputs: StreamMap<String, OutputStream>,
+    color: bool,
+) -> Result<(), Box<dyn Error>> {
+    let decorator_length = outputs.keys().map(String::len).max().unwrap_or_default();
+    while let Some((ctnr, msg)) = outputs.next().await {
+        let msg_bytes = msg?.into_bytes();
+        let msg_string = String::from_utf8_lossy(&msg_bytes);
+        // without a terminal there is no width to wrap at
+        let screen_width = crossterm::terminal::size()
+            .map(|(width, _)| width as usize)
+            .unwrap_or(usize::MAX);
+        let messages = msg_string
+            .split('\n')
+            .flat_map(|string| {
+                let wrap_at = screen_width.saturating_sub(decorator_length + 6);
+                if string.len() + decorator_length > screen_width && wrap_at > 0 {
+                    let mut split = wrap_at;
+                    while !string.is_char_boundary(split) {
+                        split -= 1;
+                    }
+                    let test = string.split_at(split);
+                    vec![test.0, test.1]
+                } else {
+                    vec![string]
+                }
+            })
+            .collect::<Vec<&str>>();
+        for message in messages {
+            if !message.is_empty() {
+                let container = format!("{:<width$}", ctnr.clone(), width = decorator_length);
+                if !color {
+                    println!("{} | {}", container, message);
+                    continue;
+                }
+                let container = if ctnr.len() < decorator_length {
+                    container.yellow()
+                } else {
+                    container.cyan()
+                };
+                println!("{} | {}", container, message);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Parses the `--since` and `--until` values of `wizard logs` into a unix
+/// timestamp. Accepts unix timestamps, RFC 3339 dates and durations such as
+/// `10m`, which are relative to now.
+pub(crate) fn parse_timestamp(value: &str) -> Result<i64, Box<dyn Error>> {
+    if let Ok(seconds) = value.parse::<f64>() {
+        return Ok(seconds as i64);
+    }
+    if let Ok(date) = DateTime::parse_from_rfc3339(value) {
+        return Ok(date.timestamp());
+    }
+    let ago =
+        parse_duration(value).map_err(|_| format!("Invalid timestamp or duration: {}", value))?;
+    Ok(Utc::now().timestamp() - ago.as_secs() as i64)
+}
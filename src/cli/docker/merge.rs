@@ -0,0 +1,313 @@
+use serde_yaml::{Mapping, Value};
+
+/// Service keys holding lists that later files extend instead of replace.
+const APPENDED_KEYS: &[&str] = &[
+    "ports",
+    "expose",
+    "dns",
+    "dns_search",
+    "env_file",
+    "cap_add",
+    "cap_drop",
+    "security_opt",
+    "extra_hosts",
+];
+
+/// Service keys holding lists of mounts, merged by their container path.
+const MOUNT_KEYS: &[&str] = &["volumes", "devices"];
+
+/// Service keys holding `KEY=VALUE` lists or mappings, merged by key.
+const KEYED_KEYS: &[&str] = &["environment", "labels", "sysctls"];
+
+/// Merges `overlay` into `base` following the compose spec rules for
+/// multiple compose files: services are merged key by key, mappings are
+/// merged recursively and any other value in `overlay` replaces the one in
+/// `base`.
+pub(crate) fn merge_compose_files(base: &mut Value, overlay: Value) {
+    let overlay = match overlay {
+        Value::Mapping(overlay) => overlay,
+        _ => return,
+    };
+    if !base.is_mapping() {
+        *base = Value::Mapping(Mapping::new());
+    }
+    let base = base.as_mapping_mut().unwrap();
+    for (key, value) in overlay {
+        if key.as_str() == Some("services") {
+            let services = base
+                .entry(key)
+                .or_insert_with(|| Value::Mapping(Mapping::new()));
+            merge_services(services, value);
+        } else {
+            merge_value(base.entry(key).or_insert(Value::Null), value);
+        }
+    }
+}
+
+fn merge_services(base: &mut Value, overlay: Value) {
+    let overlay = match overlay {
+        Value::Mapping(overlay) => overlay,
+        _ => return,
+    };
+    if !base.is_mapping() {
+        *base = Value::Mapping(Mapping::new());
+    }
+    let base = base.as_mapping_mut().unwrap();
+    for (service_name, service_config) in overlay {
+        let base_config = base.entry(service_name).or_insert(Value::Null);
+        merge_service(base_config, service_config);
+    }
+}
+
+fn merge_service(base: &mut Value, overlay: Value) {
+    let overlay = match overlay {
+        Value::Mapping(overlay) => overlay,
+        _ => return,
+    };
+    if !base.is_mapping() {
+        *base = Value::Mapping(Mapping::new());
+    }
+    let base = base.as_mapping_mut().unwrap();
+    for (key, value) in overlay {
+        let name = key.as_str().unwrap_or_default().to_string();
+        let base_value = base.entry(key).or_insert(Value::Null);
+        if base_value.is_null() {
+            *base_value = value;
+        } else if APPENDED_KEYS.contains(&name.as_str()) {
+            append_unique(base_value, value);
+        } else if MOUNT_KEYS.contains(&name.as_str()) {
+            merge_mounts(base_value, value);
+        } else if KEYED_KEYS.contains(&name.as_str()) {
+            merge_keyed(base_value, value);
+        } else if name == "depends_on" {
+            merge_depends_on(base_value, value);
+        } else if name == "command" || name == "entrypoint" {
+            *base_value = value;
+        } else {
+            merge_value(base_value, value);
+        }
+    }
+}
+
+fn merge_value(base: &mut Value, overlay: Value) {
+    match (base.as_mapping_mut(), overlay) {
+        (Some(base), Value::Mapping(overlay)) => {
+            for (key, value) in overlay {
+                merge_value(base.entry(key).or_insert(Value::Null), value);
+            }
+        }
+        (_, overlay) => *base = overlay,
+    }
+}
+
+fn append_unique(base: &mut Value, overlay: Value) {
+    let overlay = to_sequence(overlay);
+    let mut merged = to_sequence(base.clone());
+    for item in overlay {
+        if !merged.contains(&item) {
+            merged.push(item);
+        }
+    }
+    *base = Value::Sequence(merged);
+}
+
+fn merge_mounts(base: &mut Value, overlay: Value) {
+    let mut merged = to_sequence(base.clone());
+    for mount in to_sequence(overlay) {
+        let target = mount_target(&mount);
+        match merged
+            .iter()
+            .position(|existing| target.is_some() && mount_target(existing) == target)
+        {
+            Some(index) => merged[index] = mount,
+            None => merged.push(mount),
+        }
+    }
+    *base = Value::Sequence(merged);
+}
+
+/// The container path of a short (`source:target[:mode]`) or long form mount.
+fn mount_target(mount: &Value) -> Option<String> {
+    match mount {
+        Value::String(mount) => {
+            let parts = mount.split(':').collect::<Vec<_>>();
+            let target = if parts.len() > 1 { parts[1] } else { parts[0] };
+            Some(target.to_string())
+        }
+        Value::Mapping(mount) => mount
+            .get(&Value::from("target"))
+            .and_then(Value::as_str)
+            .map(String::from),
+        _ => None,
+    }
+}
+
+fn merge_keyed(base: &mut Value, overlay: Value) {
+    let mut merged = to_mapping(base.clone());
+    for (key, value) in to_mapping(overlay) {
+        // values are strings once they reach the container
+        let value = match value {
+            Value::Number(number) => Value::from(number.to_string()),
+            Value::Bool(boolean) => Value::from(boolean.to_string()),
+            value => value,
+        };
+        merged.insert(key, value);
+    }
+    *base = Value::Mapping(merged);
+}
+
+/// `depends_on` can be a list of service names or a mapping of service
+/// names to conditions. Two lists stay a list, otherwise both become
+/// mappings before they are merged.
+fn merge_depends_on(base: &mut Value, overlay: Value) {
+    if base.is_sequence() && overlay.is_sequence() {
+        return append_unique(base, overlay);
+    }
+    let as_conditions = |value: Value| -> Mapping {
+        match value {
+            Value::Sequence(services) => services
+                .into_iter()
+                .map(|service| {
+                    let mut condition = Mapping::new();
+                    condition.insert(Value::from("condition"), Value::from("service_started"));
+                    (service, Value::Mapping(condition))
+                })
+                .collect(),
+            Value::Mapping(services) => services,
+            _ => Mapping::new(),
+        }
+    };
+    let mut merged = as_conditions(base.clone());
+    for (service, condition) in as_conditions(overlay) {
+        merged.insert(service, condition);
+    }
+    *base = Value::Mapping(merged);
+}
+
+fn to_sequence(value: Value) -> Vec<Value> {
+    match value {
+        Value::Sequence(sequence) => sequence,
+        Value::Null => vec![],
+        value => vec![value],
+    }
+}
+
+/// Turns a `KEY=VALUE` list into a mapping, leaving mappings untouched.
+fn to_mapping(value: Value) -> Mapping {
+    match value {
+        Value::Mapping(mapping) => mapping,
+        Value::Sequence(sequence) => sequence
+            .into_iter()
+            .filter_map(|item| item.as_str().map(String::from))
+            .map(|item| match item.split_once('=') {
+                Some((key, value)) => (Value::from(key), Value::from(value)),
+                None => (Value::from(item), Value::Null),
+            })
+            .collect(),
+        _ => Mapping::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn yaml(input: &str) -> Value {
+        serde_yaml::from_str(input).unwrap()
+    }
+
+    /// Merges the `web` service of `overlay` into the one of `base` and
+    /// returns it.
+    fn merged_web(base: &str, overlay: &str) -> Value {
+        let mut base = yaml(&format!("services:\n  web:\n{}", base));
+        merge_compose_files(&mut base, yaml(&format!("services:\n  web:\n{}", overlay)));
+        base["services"]["web"].clone()
+    }
+
+    #[test]
+    fn replaces_scalars_and_merges_mappings() {
+        let web = merged_web(
+            "    image: ruby:3.0\n    build:\n      context: .\n      target: dev\n",
+            "    image: ruby:3.1\n    build:\n      target: prod\n",
+        );
+        assert_eq!(
+            web,
+            yaml("image: ruby:3.1\nbuild:\n  context: .\n  target: prod")
+        );
+    }
+
+    #[test]
+    fn replaces_commands() {
+        let web = merged_web("    command: [rails, s]\n", "    command: [rails, c]\n");
+        assert_eq!(web["command"], yaml("[rails, c]"));
+    }
+
+    #[test]
+    fn appends_lists_without_duplicates() {
+        let web = merged_web(
+            "    ports: ['3000:3000']\n    env_file: .env\n",
+            "    ports: ['3000:3000', '9229:9229']\n    env_file: [.env.local]\n",
+        );
+        assert_eq!(web["ports"], yaml("['3000:3000', '9229:9229']"));
+        assert_eq!(web["env_file"], yaml("[.env, .env.local]"));
+    }
+
+    #[test]
+    fn merges_mounts_by_target() {
+        let web = merged_web(
+            "    volumes: ['.:/app', 'gems:/usr/local/bundle']\n",
+            "    volumes: ['./src:/app:ro', {type: tmpfs, target: /tmp}]\n",
+        );
+        assert_eq!(
+            web["volumes"],
+            yaml("['./src:/app:ro', 'gems:/usr/local/bundle', {type: tmpfs, target: /tmp}]")
+        );
+    }
+
+    #[test]
+    fn merges_keyed_lists_and_mappings() {
+        let web = merged_web(
+            "    environment: ['RAILS_ENV=development', 'PORT=3000']\n",
+            "    environment:\n      PORT: 4000\n      DEBUG: true\n",
+        );
+        let environment = web["environment"].as_mapping().unwrap();
+        assert_eq!(environment.len(), 3);
+        assert_eq!(web["environment"]["RAILS_ENV"], yaml("development"));
+        assert_eq!(web["environment"]["PORT"], Value::from("4000"));
+        assert_eq!(web["environment"]["DEBUG"], Value::from("true"));
+    }
+
+    #[test]
+    fn merges_depends_on() {
+        let web = merged_web("    depends_on: [db]\n", "    depends_on: [redis]\n");
+        assert_eq!(web["depends_on"], yaml("[db, redis]"));
+
+        let web = merged_web(
+            "    depends_on: [db, redis]\n",
+            "    depends_on:\n      db:\n        condition: service_healthy\n",
+        );
+        assert_eq!(
+            web["depends_on"]["db"]["condition"],
+            yaml("service_healthy")
+        );
+        assert_eq!(
+            web["depends_on"]["redis"]["condition"],
+            yaml("service_started")
+        );
+    }
+
+    #[test]
+    fn adds_new_services_and_top_level_keys() {
+        let mut base = yaml("services:\n  web:\n    image: ruby\nvolumes:\n  gems: {}");
+        merge_compose_files(
+            &mut base,
+            yaml("services:\n  db:\n    image: postgres\nvolumes:\n  db-data: {}"),
+        );
+        assert_eq!(
+            base,
+            yaml(
+                "services:\n  web:\n    image: ruby\n  db:\n    image: postgres\nvolumes:\n  gems: {}\n  db-data: {}"
+            )
+        );
+    }
+}
@@ -2,63 +2,76 @@ use bollard::models::ContainerSummary;
 // use bollard::models::ContainerSummaryInner;
 use bollard::{container::ListContainersOptions, Docker};
 use crossterm::style::Stylize;
+use docker_compose_types::{Compose, Services};
 use owo_colors::OwoColorize;
-use std::collections::HashMap;
 use std::fmt::{self, Display, Formatter};
+use std::{collections::HashMap, env};
 
+pub(crate) mod build;
+pub(crate) mod build_context;
 pub(crate) mod compose;
+pub(crate) mod dependencies;
+pub(crate) mod extensions;
+pub(crate) mod interpolation;
+pub(crate) mod labels;
+pub(crate) mod logs;
+pub(crate) mod merge;
+pub(crate) mod ports;
+pub(crate) mod pull;
 pub(crate) mod utils;
+pub(crate) mod volumes;
 
-use self::compose::get_service_names_from_compose_file;
+use self::{
+    compose::{get_service_names_from_compose_file, parse_docker_compose_file},
+    labels::{project_filter, service_filter, SERVICE_LABEL},
+    utils::project_hash,
+};
 
 pub(crate) async fn docker_status() -> Result<(), Box<dyn std::error::Error>> {
+    let path = env::current_dir()?;
+    let project_hash = project_hash(path.to_str().unwrap());
+    let project_name = path.file_name().unwrap().to_str().unwrap().to_owned();
     let docker = init_docker();
-    let project_containers = get_service_names_from_compose_file()?;
-    let project_containers: Vec<&str> = project_containers.iter().map(|s| s as &str).collect();
-    let mut list_container_filters = HashMap::new();
-    list_container_filters.insert("name", project_containers.clone());
+    let service_names = get_service_names_from_compose_file()?;
 
-    let running_containers = &docker
+    let project_containers = docker
         .list_containers(Some(ListContainersOptions {
             all: true,
-            filters: list_container_filters,
+            filters: project_filter(&project_hash),
             ..Default::default()
         }))
         .await?;
 
-    if running_containers.is_empty() {
-        for container in project_containers {
+    if project_containers.is_empty() {
+        for service_name in service_names {
             println!(
                 "[Wizard]::Status - {} [{}]",
-                container.cyan(),
+                format!("{}-{}-{}", project_name, service_name, project_hash).cyan(),
                 "stopped".red()
             );
         }
         println!("[Wizard]::Status - {}", "Project not running".red());
     } else {
-        let containers_summary_vec = running_containers
-            .iter()
-            .map(|container| ContainerSummaryInnerWrapper {
-                inner: container.clone(),
-            })
+        let containers_summary_vec = project_containers
+            .into_iter()
+            .map(|container| ContainerSummaryInnerWrapper { inner: container })
             .collect::<Vec<ContainerSummaryInnerWrapper>>();
-        let stopped_containers: Vec<_> = project_containers
+        let stopped_services: Vec<_> = service_names
             .iter()
-            .filter(|item| {
+            .filter(|service_name| {
                 !containers_summary_vec
                     .iter()
-                    .map(|item| item.name())
-                    .any(|x| x == item.to_string())
+                    .any(|container| container.service().as_deref() == Some(service_name))
             })
             .collect();
         for container in containers_summary_vec {
             println!("[Wizard]::Status - {}", container);
         }
-        if !stopped_containers.is_empty() {
-            for container in stopped_containers {
+        if !stopped_services.is_empty() {
+            for service_name in stopped_services {
                 println!(
                     "[Wizard]::Status - {} [{}]",
-                    container.cyan(),
+                    format!("{}-{}-{}", project_name, service_name, project_hash).cyan(),
                     "stopped".red()
                 );
             }
@@ -75,52 +88,88 @@ pub(crate) async fn docker_status() -> Result<(), Box<dyn std::error::Error>> {
     Ok(())
 }
 
-pub async fn get_containers() -> Result<Vec<String>, Box<dyn std::error::Error>> {
-    // get only project containers
-    let docker = init_docker();
-    let project_containers = get_service_names_from_compose_file()?;
-    let project_containers: Vec<&str> = project_containers.iter().map(|s| s as &str).collect();
-    let mut list_container_filters = HashMap::new();
-    list_container_filters.insert("name", project_containers.clone());
-    let containers = &docker
+/// The container wizard created for `service_name`, running or not.
+pub(crate) async fn get_service_container(
+    docker: &Docker,
+    project_hash: &str,
+    service_name: &str,
+) -> Result<Option<ContainerSummary>, Box<dyn std::error::Error>> {
+    let containers = docker
         .list_containers(Some(ListContainersOptions {
             all: true,
-            filters: list_container_filters,
+            filters: service_filter(project_hash, service_name),
             ..Default::default()
         }))
         .await?;
-    let mut container_names = vec![];
-    for container in containers {
-        container_names.push(container.names.clone().unwrap());
-    }
-    let container_names = container_names
-        .into_iter()
-        .flatten()
-        .map(|s| s.trim_start_matches('/').to_string())
-        .collect();
-    Ok(container_names)
+    Ok(containers.into_iter().next())
 }
 
-pub async fn get_container_id(
+/// The container named `container_name`, running or not.
+pub(crate) async fn get_container_by_name(
     docker: &Docker,
     container_name: &str,
-) -> Result<String, Box<dyn std::error::Error>> {
-    // get only project containers
-    let mut list_container_filters = HashMap::new();
-    list_container_filters.insert("status", vec!["running"]);
-    list_container_filters.insert("name", vec![container_name]);
-
-    let containers = &docker
+) -> Result<Option<ContainerSummary>, Box<dyn std::error::Error>> {
+    let containers = docker
         .list_containers(Some(ListContainersOptions {
             all: true,
-            filters: list_container_filters,
+            // the name filter matches substrings unless anchored
+            filters: HashMap::from([("name".to_string(), vec![format!("^/{}$", container_name)])]),
             ..Default::default()
         }))
         .await?;
-    if let Some(container) = containers.first() {
-        Ok(container.id.clone().unwrap())
-    } else {
-        Err("Container not found".into())
+    Ok(containers.into_iter().next())
+}
+
+/// Resolves a compose service to the id of its running container. Without
+/// a service name, the one from `resolve_service_name` is used.
+pub(crate) async fn resolve_service_container(
+    docker: &Docker,
+    service_name: Option<&str>,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let service_name = resolve_service_name(service_name)?;
+    running_service_container(docker, &service_name)
+        .await?
+        .ok_or_else(|| format!("No running container for service {}", service_name).into())
+}
+
+/// Checks `service_name` is a service of the compose file. Without a service
+/// name, the service named after the project is used, or the first service
+/// of the compose file when there is none.
+pub(crate) fn resolve_service_name(
+    service_name: Option<&str>,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let path = env::current_dir()?;
+    let project_name = path.file_name().unwrap().to_str().unwrap().to_owned();
+    let Compose { services, .. } = parse_docker_compose_file()?;
+    let Services(services_map) = services.expect("Docker-compose file must have services");
+    match service_name {
+        Some(service_name) if services_map.contains_key(service_name) => {
+            Ok(service_name.to_string())
+        }
+        Some(service_name) => Err(format!("No such service: {}", service_name).into()),
+        None if services_map.contains_key(&project_name) => Ok(project_name),
+        None => services_map
+            .keys()
+            .next()
+            .cloned()
+            .ok_or_else(|| "The compose file defines no services".into()),
+    }
+}
+
+/// The id of the container of `service_name`, if it's running.
+pub(crate) async fn running_service_container(
+    docker: &Docker,
+    service_name: &str,
+) -> Result<Option<String>, Box<dyn std::error::Error>> {
+    let path = env::current_dir()?;
+    let project_hash = project_hash(path.to_str().unwrap());
+    match get_service_container(docker, &project_hash, service_name).await? {
+        Some(ContainerSummary {
+            id: Some(id),
+            state: Some(state),
+            ..
+        }) if state == "running" => Ok(Some(id)),
+        _ => Ok(None),
     }
 }
 
@@ -150,20 +199,15 @@ impl ContainerSummaryInnerWrapper {
             .collect::<Vec<&str>>()
             .join(", ")
     }
+
+    pub(crate) fn service(&self) -> Option<String> {
+        self.inner.labels.as_ref()?.get(SERVICE_LABEL).cloned()
+    }
 }
 
 impl Display for ContainerSummaryInnerWrapper {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
-        let name = &self
-            .inner
-            .names
-            .as_ref()
-            .unwrap()
-            .clone()
-            .iter()
-            .map(|name| name.strip_prefix('/').unwrap())
-            .collect::<Vec<&str>>()
-            .join(", ");
+        let name = self.name();
         let state = &self.inner.state.as_ref().unwrap();
         let image = &self.inner.image.as_ref().unwrap();
 
@@ -0,0 +1,312 @@
+use serde::Deserialize;
+use std::{
+    error::Error,
+    fmt::{self, Display, Formatter},
+};
+
+/// An entry of a service's `ports` list, either in the short
+/// `[[host_ip:]published:]target[/protocol]` form or the long form.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub(crate) enum Port {
+    Number(u16),
+    Short(String),
+    Long(LongPort),
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub(crate) struct LongPort {
+    pub(crate) target: PortNumber,
+    pub(crate) published: Option<PortNumber>,
+    pub(crate) host_ip: Option<String>,
+    pub(crate) protocol: Option<String>,
+}
+
+/// Port numbers may be written as yaml integers or as strings, which is
+/// also how ranges such as `"9000-9005"` are written.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub(crate) enum PortNumber {
+    Number(u16),
+    Text(String),
+}
+
+impl Display for PortNumber {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            PortNumber::Number(number) => write!(f, "{}", number),
+            PortNumber::Text(text) => write!(f, "{}", text),
+        }
+    }
+}
+
+impl Display for Port {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Port::Number(number) => write!(f, "{}", number),
+            Port::Short(port) => write!(f, "{}", port),
+            Port::Long(port) => {
+                write!(f, "target: {}", port.target)?;
+                if let Some(published) = &port.published {
+                    write!(f, ", published: {}", published)?;
+                }
+                if let Some(host_ip) = &port.host_ip {
+                    write!(f, ", host_ip: {}", host_ip)?;
+                }
+                if let Some(protocol) = &port.protocol {
+                    write!(f, ", protocol: {}", protocol)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// A single container port and the host port it is published on.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct PortMapping {
+    pub(crate) host_ip: Option<String>,
+    /// A host port or host port range, `None` lets docker pick one
+    pub(crate) published: Option<String>,
+    pub(crate) target: u16,
+    pub(crate) protocol: String,
+}
+
+impl PortMapping {
+    /// The key docker uses for the container port, e.g. `3000/tcp`.
+    pub(crate) fn container_port(&self) -> String {
+        format!("{}/{}", self.target, self.protocol)
+    }
+}
+
+impl Port {
+    /// Expands the entry into one mapping per container port.
+    pub(crate) fn mappings(&self) -> Result<Vec<PortMapping>, Box<dyn Error>> {
+        match self {
+            Port::Number(target) => Ok(vec![PortMapping {
+                host_ip: None,
+                published: None,
+                target: *target,
+                protocol: "tcp".to_string(),
+            }]),
+            Port::Short(port) => parse_short_port(port),
+            Port::Long(LongPort {
+                target,
+                published,
+                host_ip,
+                protocol,
+            }) => expand_ranges(
+                host_ip.clone(),
+                published.as_ref().map(|p| p.to_string()).as_deref(),
+                &target.to_string(),
+                protocol.as_deref().unwrap_or("tcp"),
+            ),
+        }
+    }
+}
+
+fn parse_short_port(port: &str) -> Result<Vec<PortMapping>, Box<dyn Error>> {
+    let (port, protocol) = match port.rsplit_once('/') {
+        Some((port, protocol)) => (port, protocol),
+        None => (port, "tcp"),
+    };
+    let (rest, target) = match port.rsplit_once(':') {
+        Some((rest, target)) => (Some(rest), target),
+        None => (None, port),
+    };
+    let (host_ip, published) = match rest {
+        None => (None, None),
+        Some(rest) => match rest.rsplit_once(':') {
+            // ipv6 host addresses are wrapped in brackets, e.g. [::1]:8080:80
+            Some((host_ip, published)) => (
+                Some(host_ip.trim_start_matches('[').trim_end_matches(']')),
+                Some(published),
+            ),
+            None => (None, Some(rest)),
+        },
+    };
+    let published = published.filter(|published| !published.is_empty());
+    expand_ranges(host_ip.map(String::from), published, target, protocol)
+}
+
+fn expand_ranges(
+    host_ip: Option<String>,
+    published: Option<&str>,
+    target: &str,
+    protocol: &str,
+) -> Result<Vec<PortMapping>, Box<dyn Error>> {
+    if !["tcp", "udp", "sctp"].contains(&protocol) {
+        return Err(format!("unknown protocol {}", protocol).into());
+    }
+    let (target_start, target_end) = parse_range(target)?;
+    let published_range = published.map(parse_range).transpose()?;
+    let target_count = target_end - target_start;
+    let mapping = |target: u16, published: Option<String>| PortMapping {
+        host_ip: host_ip.clone(),
+        published,
+        target,
+        protocol: protocol.to_string(),
+    };
+    match published_range {
+        // a single container port can be published on any port of a host range
+        Some((published_start, published_end)) if target_count == 0 => Ok(vec![mapping(
+            target_start,
+            Some(if published_start == published_end {
+                published_start.to_string()
+            } else {
+                format!("{}-{}", published_start, published_end)
+            }),
+        )]),
+        Some((published_start, published_end)) => {
+            if published_end - published_start != target_count {
+                return Err("published and target port ranges differ in size".into());
+            }
+            Ok((0..=target_count)
+                .map(|offset| {
+                    mapping(
+                        target_start + offset,
+                        Some((published_start + offset).to_string()),
+                    )
+                })
+                .collect())
+        }
+        None => Ok((target_start..=target_end)
+            .map(|target| mapping(target, None))
+            .collect()),
+    }
+}
+
+fn parse_range(range: &str) -> Result<(u16, u16), Box<dyn Error>> {
+    let parse = |port: &str| {
+        port.trim()
+            .parse::<u16>()
+            .map_err(|_| format!("invalid port {}", port))
+    };
+    match range.split_once('-') {
+        Some((start, end)) => {
+            let (start, end) = (parse(start)?, parse(end)?);
+            if end < start {
+                return Err(format!("invalid port range {}", range).into());
+            }
+            Ok((start, end))
+        }
+        None => {
+            let port = parse(range)?;
+            Ok((port, port))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mappings(port: &str) -> Vec<PortMapping> {
+        Port::Short(port.to_string()).mappings().unwrap()
+    }
+
+    fn mapping(
+        host_ip: Option<&str>,
+        published: Option<&str>,
+        target: u16,
+        protocol: &str,
+    ) -> PortMapping {
+        PortMapping {
+            host_ip: host_ip.map(String::from),
+            published: published.map(String::from),
+            target,
+            protocol: protocol.to_string(),
+        }
+    }
+
+    #[test]
+    fn parses_target_only() {
+        assert_eq!(mappings("3000"), vec![mapping(None, None, 3000, "tcp")]);
+        assert_eq!(
+            Port::Number(3000).mappings().unwrap(),
+            vec![mapping(None, None, 3000, "tcp")]
+        );
+    }
+
+    #[test]
+    fn parses_published_and_target() {
+        assert_eq!(
+            mappings("8080:80"),
+            vec![mapping(None, Some("8080"), 80, "tcp")]
+        );
+    }
+
+    #[test]
+    fn parses_host_ips() {
+        assert_eq!(
+            mappings("127.0.0.1:8080:80"),
+            vec![mapping(Some("127.0.0.1"), Some("8080"), 80, "tcp")]
+        );
+        assert_eq!(
+            mappings("[::1]:8080:80"),
+            vec![mapping(Some("::1"), Some("8080"), 80, "tcp")]
+        );
+        assert_eq!(
+            mappings("127.0.0.1::80"),
+            vec![mapping(Some("127.0.0.1"), None, 80, "tcp")]
+        );
+    }
+
+    #[test]
+    fn parses_protocols() {
+        assert_eq!(
+            mappings("53:53/udp"),
+            vec![mapping(None, Some("53"), 53, "udp")]
+        );
+        assert_eq!(mappings("9/sctp")[0].container_port(), "9/sctp");
+        assert!(Port::Short("80/http".to_string()).mappings().is_err());
+    }
+
+    #[test]
+    fn expands_ranges() {
+        assert_eq!(
+            mappings("9000-9001:8000-8001"),
+            vec![
+                mapping(None, Some("9000"), 8000, "tcp"),
+                mapping(None, Some("9001"), 8001, "tcp"),
+            ]
+        );
+        assert_eq!(
+            mappings("8000-8001"),
+            vec![
+                mapping(None, None, 8000, "tcp"),
+                mapping(None, None, 8001, "tcp")
+            ]
+        );
+        assert_eq!(
+            mappings("9000-9005:80"),
+            vec![mapping(None, Some("9000-9005"), 80, "tcp")]
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_ranges() {
+        assert!(Port::Short("9000-9002:8000-8001".to_string())
+            .mappings()
+            .is_err());
+        assert!(Port::Short("8001-8000".to_string()).mappings().is_err());
+        assert!(Port::Short("http".to_string()).mappings().is_err());
+    }
+
+    #[test]
+    fn parses_long_form() {
+        let port: Port = serde_yaml::from_str(
+            "target: 80\npublished: \"8080-8081\"\nhost_ip: 0.0.0.0\nprotocol: udp",
+        )
+        .unwrap();
+        assert_eq!(
+            port.mappings().unwrap(),
+            vec![mapping(Some("0.0.0.0"), Some("8080-8081"), 80, "udp")]
+        );
+        let port: Port = serde_yaml::from_str("target: 80").unwrap();
+        assert_eq!(
+            port.mappings().unwrap(),
+            vec![mapping(None, None, 80, "tcp")]
+        );
+    }
+}
@@ -0,0 +1,146 @@
+use bollard::{image::CreateImageOptions, Docker};
+use crossterm::style::Stylize;
+use futures_util::StreamExt;
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use serde::Deserialize;
+use std::{collections::HashMap, error::Error, sync::Arc};
+
+/// The service `pull_policy` key. `if_not_present` is the older spelling
+/// of `missing`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum PullPolicy {
+    Always,
+    #[default]
+    #[serde(alias = "if_not_present")]
+    Missing,
+    Never,
+    Build,
+}
+
+/// Makes sure `image` is available locally before a container is created
+/// from it, pulling it when `policy` asks for it.
+pub(crate) async fn ensure_image(
+    docker: &Docker,
+    service_name: &str,
+    image: &str,
+    policy: PullPolicy,
+) -> Result<(), Box<dyn Error>> {
+    match policy {
+        PullPolicy::Always => pull_image(docker, image).await,
+        PullPolicy::Missing if !image_exists(docker, image).await => {
+            pull_image(docker, image).await
+        }
+        PullPolicy::Never if !image_exists(docker, image).await => Err(format!(
+            "Image {} for service {} is not present locally and its pull_policy is never",
+            image, service_name
+        )
+        .into()),
+        PullPolicy::Build => Err(format!(
+            "Service {} has pull_policy build but no build section",
+            service_name
+        )
+        .into()),
+        _ => Ok(()),
+    }
+}
+
+pub(crate) async fn image_exists(docker: &Docker, image: &str) -> bool {
+    docker.inspect_image(image).await.is_ok()
+}
+
+/// Pulls `image`, showing a progress bar for every layer being fetched.
+pub(crate) async fn pull_image(docker: &Docker, image: &str) -> Result<(), Box<dyn Error>> {
+    let (from_image, tag) = split_tag(image);
+    let options = CreateImageOptions {
+        from_image,
+        tag: tag.clone(),
+        ..Default::default()
+    };
+    let multi = Arc::new(MultiProgress::new());
+    let pb = multi.add(ProgressBar::new_spinner().with_message(format!("Pulling {}", image)));
+    pb.enable_steady_tick(100);
+    // the bars are only drawn while `join` runs, which returns once every
+    // bar has finished
+    let drawing = tokio::task::spawn_blocking({
+        let multi = multi.clone();
+        move || multi.join()
+    });
+    let layer_style = ProgressStyle::default_bar()
+        .template("  {prefix} {msg:<12} [{bar:30}] {bytes}/{total_bytes}")
+        .progress_chars("=> ");
+    let mut layers: HashMap<String, ProgressBar> = HashMap::new();
+
+    let mut pulling = docker.create_image(Some(options), None, None);
+    let mut result = Ok(());
+    while let Some(info) = pulling.next().await {
+        let info = match info {
+            Ok(info) => info,
+            Err(e) => {
+                result = Err(e.to_string());
+                break;
+            }
+        };
+        if let Some(error) = info.error {
+            result = Err(error);
+            break;
+        }
+        let (id, status) = match (info.id, info.status) {
+            // the first message is about the tag rather than a layer
+            (Some(id), Some(status)) if id != tag => (id, status),
+            _ => continue,
+        };
+        let layer = layers.entry(id.clone()).or_insert_with(|| {
+            multi.add(
+                ProgressBar::new(0)
+                    .with_style(layer_style.clone())
+                    .with_prefix(id),
+            )
+        });
+        match info.progress_detail {
+            Some(detail) if detail.total.unwrap_or_default() > 0 => {
+                layer.set_length(detail.total.unwrap_or_default() as u64);
+                layer.set_position(detail.current.unwrap_or_default() as u64);
+            }
+            _ => {}
+        }
+        if status == "Pull complete" || status == "Already exists" {
+            layer.finish_with_message(status);
+        } else {
+            layer.set_message(status);
+        }
+    }
+    for layer in layers.values() {
+        if !layer.is_finished() {
+            layer.finish();
+        }
+    }
+    match result {
+        Ok(()) => {
+            pb.finish_with_message(format!("{} {} [{}]", "✔".green(), image, "pulled".green()));
+            drawing.await??;
+            Ok(())
+        }
+        Err(error) => {
+            pb.abandon_with_message(format!("{} {} [{}]", "✘".red(), image, "failed".red()));
+            drawing.await??;
+            Err(format!("Could not pull {}: {}", image, error).into())
+        }
+    }
+}
+
+/// Splits `image` into the repository and tag docker's pull API expects.
+/// Images referenced by digest are passed whole.
+fn split_tag(image: &str) -> (String, String) {
+    if image.contains('@') {
+        return (image.to_string(), String::new());
+    }
+    let name_start = image.rfind('/').map(|i| i + 1).unwrap_or(0);
+    match image[name_start..].rfind(':') {
+        Some(i) => (
+            image[..name_start + i].to_string(),
+            image[name_start + i + 1..].to_string(),
+        ),
+        None => (image.to_string(), String::from("latest")),
+    }
+}
@@ -1,6 +1,12 @@
 use std::{
     error::Error,
-    io::{stdout, Write},
+    io::{self, stderr, stdout, IsTerminal, Write},
+    panic,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Once,
+    },
     time::Duration,
 };
 
@@ -9,13 +15,23 @@ use async_std::{
     prelude::StreamExt,
 };
 use bollard::{
-    container::RemoveContainerOptions,
+    container::{
+        AttachContainerOptions, AttachContainerResults, LogOutput, RemoveContainerOptions,
+        ResizeContainerTtyOptions, WaitContainerOptions,
+    },
     exec::{CreateExecOptions, ResizeExecOptions, StartExecResults},
     Docker,
 };
 use crossterm::terminal::{disable_raw_mode, enable_raw_mode, size};
+use futures_util::Stream;
 use sha2::{Digest, Sha256};
-use tokio::{io::AsyncWriteExt, spawn, time::sleep};
+use tokio::{
+    io::{AsyncWrite, AsyncWriteExt},
+    signal::unix::{signal, SignalKind},
+    spawn,
+    task::JoinHandle,
+    time::sleep,
+};
 
 use super::init_docker;
 
@@ -26,24 +42,78 @@ pub(crate) struct ExecConfig<'a> {
     pub(crate) command_args: &'a [&'a str],
     pub(crate) attach_stdin: Option<bool>,
     pub(crate) env: Option<Vec<&'a str>>,
+    /// allocate a pseudo-TTY. Decided by whether stdin and stdout are
+    /// terminals when not set
+    pub(crate) tty: Option<bool>,
+    /// key sequence detaching from an interactive exec, such as
+    /// `ctrl-p,ctrl-q`, which is the default
+    pub(crate) detach_keys: Option<&'a str>,
+}
+
+/// Docker's own default for the detach key sequence.
+const DEFAULT_DETACH_KEYS: &str = "ctrl-p,ctrl-q";
+
+/// How much of an exec's output `ExecResult` keeps. Interactive sessions
+/// can run for a long time, and only the last lines are of interest.
+const CAPTURED_OUTPUT_LIMIT: usize = 8 * 1024;
+
+/// How often the first resize of a TTY is tried, like the docker CLI does.
+const INITIAL_RESIZE_ATTEMPTS: u64 = 10;
+
+/// How a command run with `run_container_command` ended.
+#[derive(Debug, Clone)]
+pub(crate) struct ExecResult {
+    pub(crate) exit_code: i64,
+    /// the end of what the command wrote to stdout and stderr, at most
+    /// `CAPTURED_OUTPUT_LIMIT` bytes
+    pub(crate) output: Vec<u8>,
+    /// the user detached with the detach keys, leaving the command running.
+    /// The exit code is 0 then
+    pub(crate) detached: bool,
+}
+
+impl ExecResult {
+    pub(crate) fn success(&self) -> bool {
+        self.detached || self.exit_code == 0
+    }
+
+    /// The last line the command printed, usually the one explaining why
+    /// it failed.
+    pub(crate) fn last_line(&self) -> String {
+        String::from_utf8_lossy(&self.output)
+            .lines()
+            .map(str::trim)
+            .rev()
+            .find(|line| !line.is_empty())
+            .unwrap_or_default()
+            .to_string()
+    }
+}
+
+/// Whether wizard runs attached to a terminal, as opposed to CI or a pipe.
+pub(crate) fn is_interactive() -> bool {
+    io::stdin().is_terminal() && io::stdout().is_terminal()
 }
 
 pub(crate) async fn run_container_command(
     docker: &Docker,
     id: &str,
     config: ExecConfig<'_>,
-) -> Result<(), Box<dyn Error>> {
-    let tty_size = size()?;
-
+) -> Result<ExecResult, Box<dyn Error>> {
     let ExecConfig {
         user,
         work_dir,
         command_args,
         attach_stdin,
         env,
+        tty,
+        detach_keys,
     } = config;
-    let cmd: Vec<&str> = command_args.iter().copied().collect();
+    let cmd: Vec<&str> = command_args.to_vec();
     let attach_stdin = attach_stdin == Some(true);
+    let tty = tty.unwrap_or_else(is_interactive);
+    let detach_keys = detach_keys.unwrap_or(DEFAULT_DETACH_KEYS);
+    let detach_sequence = parse_detach_keys(detach_keys)?;
     let execution = docker
         .create_exec(
             id,
@@ -51,69 +121,332 @@ pub(crate) async fn run_container_command(
                 attach_stdout: Some(true),
                 attach_stderr: Some(true),
                 attach_stdin: Some(attach_stdin),
-                tty: Some(true),
+                tty: Some(tty),
                 user,
                 env,
                 working_dir: work_dir,
                 cmd: Some(cmd),
+                detach_keys: (tty && attach_stdin).then_some(detach_keys),
                 ..Default::default()
             },
         )
         .await?
         .id;
 
-    if let StartExecResults::Attached {
-        mut output,
-        mut input,
-    } = docker.start_exec(&execution, None).await?
-    {
-        if attach_stdin {
-            spawn(async move {
-                let mut stdin = stdin().bytes();
-
-                loop {
-                    if let Some(Ok(byte)) = stdin.next().await {
-                        input.write_all(&[byte]).await.ok();
-                    } else {
-                        sleep(Duration::from_nanos(10)).await;
-                    }
-                }
-            });
-        };
-        enable_raw_mode()?;
-        let mut stdout = stdout();
-        let mut stdout_text = vec![];
-        while let Some(Ok(output)) = output.next().await {
-            stdout_text = vec![output.clone().into_bytes()];
-            stdout.write_all(output.into_bytes().as_ref())?;
-            stdout.flush()?;
+    let (output, input) = match docker.start_exec(&execution, None).await? {
+        StartExecResults::Attached { output, input } => (output, input),
+        StartExecResults::Detached => unreachable!(),
+    };
+    let (output, detached) = if tty {
+        let input = attach_stdin.then_some((input, detach_sequence));
+        stream_tty(docker, &execution, output, input).await?
+    } else {
+        (stream_plain(output, input, attach_stdin).await?, false)
+    };
+    let exit_code = if detached {
+        0
+    } else {
+        exec_exit_code(docker, &execution).await?
+    };
+    Ok(ExecResult {
+        exit_code,
+        output,
+        detached,
+    })
+}
+
+/// Parses a detach key sequence such as `ctrl-p,ctrl-q` into the bytes the
+/// terminal sends for it. Keys are either a single character or `ctrl-`
+/// followed by a letter or one of `@[\]^_`.
+pub(crate) fn parse_detach_keys(keys: &str) -> Result<Vec<u8>, Box<dyn Error>> {
+    let invalid = || format!("Invalid detach keys: {}", keys);
+    keys.split(',')
+        .map(|key| match key.strip_prefix("ctrl-").map(str::as_bytes) {
+            Some([letter @ b'a'..=b'z']) => Ok(letter - b'a' + 1),
+            Some([symbol @ (b'@' | b'[' | b'\\' | b']' | b'^' | b'_')]) => Ok(symbol - b'@'),
+            None if key.len() == 1 => Ok(key.as_bytes()[0]),
+            _ => Err(invalid().into()),
+        })
+        .collect()
+}
+
+/// The exit code of a finished exec. Docker may report the exec as still
+/// running for a moment after its output ended.
+async fn exec_exit_code(docker: &Docker, execution: &str) -> Result<i64, Box<dyn Error>> {
+    for _ in 0..50 {
+        if let Some(exit_code) = docker.inspect_exec(execution).await?.exit_code {
+            return Ok(exit_code);
+        }
+        sleep(Duration::from_millis(100)).await;
+    }
+    Err("The command did not report an exit code".into())
+}
+
+type ExecOutput = Pin<Box<dyn Stream<Item = Result<LogOutput, bollard::errors::Error>> + Send>>;
+type ExecInput = Pin<Box<dyn AsyncWrite + Send>>;
+
+/// Forwards an exec's output to the terminal in raw mode, and stdin to the
+/// exec along with its detach key sequence when `input` is given. Returns
+/// the end of the output and whether the user detached.
+async fn stream_tty(
+    docker: &Docker,
+    execution: &str,
+    mut output: ExecOutput,
+    input: Option<(ExecInput, Vec<u8>)>,
+) -> Result<(Vec<u8>, bool), Box<dyn Error>> {
+    let _raw_mode = RawModeGuard::enable()?;
+    let resizing = follow_terminal_size(docker.clone(), TtyTarget::Exec(execution.to_string()));
+    let detached = Arc::new(AtomicBool::new(false));
+    let forwarding = input.map(|(input, detach_sequence)| {
+        spawn(forward_tty_stdin(input, detach_sequence, detached.clone()))
+    });
+    let mut captured = vec![];
+    let streamed = write_output(&mut output, &mut captured).await;
+    resizing.abort();
+    if let Some(forwarding) = forwarding {
+        forwarding.abort();
+    }
+    streamed?;
+    Ok((captured, detached.load(Ordering::SeqCst)))
+}
+
+/// Like `forward_stdin`, but notes in `detached` when the user typed the
+/// detach sequence. Docker ends the exec's output once it sees it.
+async fn forward_tty_stdin(
+    mut input: ExecInput,
+    detach_sequence: Vec<u8>,
+    detached: Arc<AtomicBool>,
+) {
+    let mut stdin = stdin();
+    let mut buffer = [0; 1024];
+    let mut matched = 0;
+    while let Ok(read) = stdin.read(&mut buffer).await {
+        if read == 0 || input.write_all(&buffer[..read]).await.is_err() {
+            break;
+        }
+        for byte in &buffer[..read] {
+            if *byte == detach_sequence[matched] {
+                matched += 1;
+            } else {
+                matched = usize::from(*byte == detach_sequence[0]);
+            }
+            if matched == detach_sequence.len() {
+                detached.store(true, Ordering::SeqCst);
+                return;
+            }
         }
+    }
+    input.shutdown().await.ok();
+}
+
+/// Puts the terminal in raw mode for as long as it lives, restoring it when
+/// dropped, which includes returning early with an error. Panics restore it
+/// through a panic hook, as they may abort without unwinding.
+struct RawModeGuard;
+
+impl RawModeGuard {
+    fn enable() -> Result<Self, Box<dyn Error>> {
+        static RESTORE_ON_PANIC: Once = Once::new();
+        RESTORE_ON_PANIC.call_once(|| {
+            let default_hook = panic::take_hook();
+            panic::set_hook(Box::new(move |info| {
+                disable_raw_mode().ok();
+                default_hook(info);
+            }));
+        });
+        enable_raw_mode()?;
+        Ok(RawModeGuard)
+    }
+}
 
-        let inspect_exec = docker.inspect_exec(&execution).await?;
+impl Drop for RawModeGuard {
+    fn drop(&mut self) {
+        disable_raw_mode().ok();
+    }
+}
+
+/// What `follow_terminal_size` resizes.
+enum TtyTarget {
+    Exec(String),
+    Container(String),
+}
 
-        if inspect_exec.exit_code.is_none() {
+/// Resizes the TTY of `target` to the terminal's size now and on every
+/// SIGWINCH, until the returned task is aborted.
+fn follow_terminal_size(docker: Docker, target: TtyTarget) -> JoinHandle<()> {
+    spawn(async move {
+        let mut window_changes = signal(SignalKind::window_change()).ok();
+        // the process may still be starting, which fails the first resize
+        for attempt in 1..=INITIAL_RESIZE_ATTEMPTS {
+            if resize_tty(&docker, &target).await {
+                break;
+            }
+            sleep(Duration::from_millis(10 * attempt)).await;
+        }
+        while let Some(window_changes) = window_changes.as_mut() {
+            if window_changes.recv().await.is_none() {
+                break;
+            }
+            // resizing fails after the process ended
+            resize_tty(&docker, &target).await;
+        }
+    })
+}
+
+/// Resizes the TTY of `target` to the terminal's size, returning whether
+/// docker accepted it.
+async fn resize_tty(docker: &Docker, target: &TtyTarget) -> bool {
+    let (width, height) = match size() {
+        Ok(size) => size,
+        Err(_) => return false,
+    };
+    let resized = match target {
+        TtyTarget::Exec(id) => {
+            docker
+                .resize_exec(id, ResizeExecOptions { height, width })
+                .await
+        }
+        TtyTarget::Container(id) => {
             docker
-                .resize_exec(
-                    &execution,
-                    ResizeExecOptions {
-                        height: tty_size.1,
-                        width: tty_size.0,
-                    },
-                )
-                .await?;
+                .resize_container_tty(id, ResizeContainerTtyOptions { width, height })
+                .await
         }
-        disable_raw_mode()?;
-        if let Some(code) = inspect_exec.exit_code {
-            if code != 0 {
-                return Err(String::from_utf8(stdout_text[0].to_vec()).unwrap().into());
+    };
+    resized.is_ok()
+}
+
+/// Forwards an exec's stdout and stderr to wizard's own, and stdin to the
+/// exec until it ends. Returns the end of its output.
+async fn stream_plain(
+    mut output: ExecOutput,
+    input: ExecInput,
+    attach_stdin: bool,
+) -> Result<Vec<u8>, Box<dyn Error>> {
+    let forwarding = attach_stdin.then(|| spawn(forward_stdin(input)));
+    let mut captured = vec![];
+    let streamed = write_output(&mut output, &mut captured).await;
+    if let Some(forwarding) = forwarding {
+        forwarding.abort();
+    }
+    streamed?;
+    Ok(captured)
+}
+
+/// Writes stderr output to stderr and everything else to stdout, keeping
+/// the last `CAPTURED_OUTPUT_LIMIT` bytes in `captured`.
+async fn write_output(
+    output: &mut ExecOutput,
+    captured: &mut Vec<u8>,
+) -> Result<(), Box<dyn Error>> {
+    while let Some(output) = output.next().await {
+        match output? {
+            LogOutput::StdErr { message } => {
+                stderr().write_all(&message)?;
+                stderr().flush()?;
+                capture(captured, &message);
+            }
+            output => {
+                let message = output.into_bytes();
+                stdout().write_all(&message)?;
+                stdout().flush()?;
+                capture(captured, &message);
             }
         }
-    } else {
-        unreachable!();
     }
     Ok(())
 }
 
+fn capture(captured: &mut Vec<u8>, message: &[u8]) {
+    captured.extend_from_slice(message);
+    let overflow = captured.len().saturating_sub(CAPTURED_OUTPUT_LIMIT);
+    captured.drain(..overflow);
+}
+
+/// Copies wizard's stdin to `input` and closes it once stdin ends, so the
+/// process reading it sees the end of its input.
+async fn forward_stdin(mut input: ExecInput) {
+    let mut stdin = stdin();
+    let mut buffer = [0; 1024];
+    while let Ok(read) = stdin.read(&mut buffer).await {
+        if read == 0 || input.write_all(&buffer[..read]).await.is_err() {
+            break;
+        }
+    }
+    input.shutdown().await.ok();
+}
+
+/// Parses a compose duration such as `1m30s`, `10s` or `500ms`.
+pub(crate) fn parse_duration(duration: &str) -> Result<Duration, Box<dyn Error>> {
+    let invalid = || format!("Invalid duration: {}", duration);
+    let mut total = Duration::ZERO;
+    let mut rest = duration.trim();
+    if rest.is_empty() {
+        return Err(invalid().into());
+    }
+    while !rest.is_empty() {
+        let digits = rest
+            .find(|c: char| !c.is_ascii_digit() && c != '.')
+            .ok_or_else(invalid)?;
+        let (amount, tail) = rest.split_at(digits);
+        let amount: f64 = amount.parse().map_err(|_| invalid())?;
+        let unit_length = tail
+            .find(|c: char| c.is_ascii_digit())
+            .unwrap_or(tail.len());
+        let (unit, tail) = tail.split_at(unit_length);
+        let seconds = match unit {
+            "h" => 3600.0,
+            "m" => 60.0,
+            "s" => 1.0,
+            "ms" => 1e-3,
+            "us" => 1e-6,
+            "ns" => 1e-9,
+            _ => return Err(invalid().into()),
+        };
+        total += Duration::from_secs_f64(amount * seconds);
+        rest = tail;
+    }
+    Ok(total)
+}
+
+/// Attaches to a created container, starts it and forwards stdin and the
+/// container's output until it exits. Returns the container's exit code.
+pub(crate) async fn run_attached(
+    docker: &Docker,
+    id: &str,
+    tty: bool,
+) -> Result<i64, Box<dyn Error>> {
+    let AttachContainerResults { mut output, input } = docker
+        .attach_container(
+            id,
+            Some(AttachContainerOptions::<String> {
+                stdin: Some(true),
+                stdout: Some(true),
+                stderr: Some(true),
+                stream: Some(true),
+                ..Default::default()
+            }),
+        )
+        .await?;
+    docker.start_container::<String>(id, None).await?;
+    let raw_mode = tty.then(RawModeGuard::enable).transpose()?;
+    let resizing =
+        tty.then(|| follow_terminal_size(docker.clone(), TtyTarget::Container(id.to_string())));
+    let forwarding = spawn(forward_stdin(input));
+    let forwarded = write_output(&mut output, &mut vec![]).await;
+    drop(raw_mode);
+    if let Some(resizing) = resizing {
+        resizing.abort();
+    }
+    forwarding.abort();
+    forwarded?;
+    let exit = docker
+        .wait_container(id, None::<WaitContainerOptions<String>>)
+        .next()
+        .await
+        .ok_or("Container exited without a status")??;
+    Ok(exit.status_code)
+}
+
 pub(crate) fn project_hash(folder: &str) -> String {
     let mut hasher = Sha256::new();
     hasher.update(folder);
@@ -137,11 +470,60 @@ pub(crate) async fn remove_container(id: &str) {
             }),
         )
         .await;
-    if remove_result.is_err() {
-        println!("Error removing {}: {}", id, remove_result.unwrap_err());
+    if let Err(err) = remove_result {
+        println!("Error removing {}: {}", id, err);
         println!(
             "You may need to remove the container manually using \"docker rm {}\"",
             id
         );
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_durations() {
+        assert_eq!(parse_duration("10s").unwrap(), Duration::from_secs(10));
+        assert_eq!(parse_duration("1m30s").unwrap(), Duration::from_secs(90));
+        assert_eq!(parse_duration("2h").unwrap(), Duration::from_secs(7200));
+        assert_eq!(parse_duration("500ms").unwrap(), Duration::from_millis(500));
+        assert_eq!(parse_duration("1.5s").unwrap(), Duration::from_millis(1500));
+        assert_eq!(parse_duration(" 20us ").unwrap(), Duration::from_micros(20));
+    }
+
+    #[test]
+    fn rejects_invalid_durations() {
+        for duration in ["", "10", "s", "10x", "1m30", "ten seconds"] {
+            assert!(parse_duration(duration).is_err(), "{} parsed", duration);
+        }
+    }
+
+    #[test]
+    fn parses_detach_keys() {
+        assert_eq!(parse_detach_keys("ctrl-p,ctrl-q").unwrap(), vec![16, 17]);
+        assert_eq!(parse_detach_keys("ctrl-a").unwrap(), vec![1]);
+        assert_eq!(
+            parse_detach_keys("ctrl-@,ctrl-[,ctrl-_").unwrap(),
+            vec![0, 27, 31]
+        );
+        assert_eq!(parse_detach_keys("ctrl-\\").unwrap(), vec![28]);
+        assert_eq!(parse_detach_keys("a,ctrl-z").unwrap(), vec![b'a', 26]);
+    }
+
+    #[test]
+    fn rejects_invalid_detach_keys() {
+        for keys in [
+            "",
+            "ctrl-",
+            "ctrl-1",
+            "ctrl-ab",
+            "ab",
+            "ctrl-p,,ctrl-q",
+            "alt-x",
+        ] {
+            assert!(parse_detach_keys(keys).is_err(), "{} parsed", keys);
+        }
+    }
+}
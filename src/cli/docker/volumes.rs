@@ -0,0 +1,523 @@
+use bollard::models::{
+    Mount, MountBindOptions, MountBindOptionsPropagationEnum, MountTmpfsOptions, MountTypeEnum,
+    MountVolumeOptions,
+};
+use indexmap::IndexMap;
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    env,
+    error::Error,
+    fmt::{self, Display, Formatter},
+    path::{Component, PathBuf},
+};
+
+/// An entry of a service's `volumes` list, either in the short
+/// `[source:]target[:mode]` form or the long form.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub(crate) enum ServiceVolume {
+    Short(String),
+    Long(LongVolume),
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub(crate) struct LongVolume {
+    #[serde(rename = "type")]
+    pub(crate) typ: String,
+    pub(crate) source: Option<String>,
+    pub(crate) target: String,
+    #[serde(default)]
+    pub(crate) read_only: bool,
+    pub(crate) consistency: Option<String>,
+    pub(crate) bind: Option<BindOptions>,
+    pub(crate) volume: Option<VolumeOptions>,
+    pub(crate) tmpfs: Option<TmpfsOptions>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub(crate) struct BindOptions {
+    pub(crate) propagation: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub(crate) struct VolumeOptions {
+    #[serde(default)]
+    pub(crate) nocopy: bool,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub(crate) struct TmpfsOptions {
+    pub(crate) size: Option<ByteSize>,
+    pub(crate) mode: Option<i64>,
+}
+
+/// Sizes may be written as a number of bytes or with a unit, e.g. `64m`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub(crate) enum ByteSize {
+    Bytes(i64),
+    Text(String),
+}
+
+/// The service `tmpfs` key, a single path or a list of `path[:options]`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub(crate) enum Tmpfs {
+    Simple(String),
+    List(Vec<String>),
+}
+
+/// A named volume declared under the top-level `volumes` key.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub(crate) struct ProjectVolume {
+    pub(crate) name: Option<String>,
+    pub(crate) driver: Option<String>,
+    pub(crate) driver_opts: Option<IndexMap<String, String>>,
+    pub(crate) labels: Option<IndexMap<String, String>>,
+    #[serde(default)]
+    pub(crate) external: External,
+}
+
+/// The volume `external` key, `true` or the legacy `{ name: foo }` form,
+/// which also gives the volume's name.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub(crate) enum External {
+    Flag(bool),
+    Named { name: String },
+}
+
+impl Default for External {
+    fn default() -> Self {
+        External::Flag(false)
+    }
+}
+
+impl Display for ServiceVolume {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            ServiceVolume::Short(volume) => write!(f, "{}", volume),
+            ServiceVolume::Long(volume) => write!(
+                f,
+                "type: {}, source: {}, target: {}",
+                volume.typ,
+                volume.source.as_deref().unwrap_or_default(),
+                volume.target
+            ),
+        }
+    }
+}
+
+/// How a service volume ends up on the container. The mounts API has no
+/// SELinux relabeling, so binds using the `z`/`Z` modes are passed to docker
+/// in the `source:target:mode` bind format instead.
+#[derive(Debug, Clone)]
+pub(crate) enum VolumeMount {
+    Mount(Box<Mount>),
+    Bind(String),
+}
+
+impl ServiceVolume {
+    pub(crate) fn to_mount(
+        &self,
+        project_name: &str,
+        project_volumes: &IndexMap<String, ProjectVolume>,
+    ) -> Result<VolumeMount, Box<dyn Error>> {
+        match self {
+            ServiceVolume::Short(volume) => {
+                parse_short_volume(volume, project_name, project_volumes)
+            }
+            ServiceVolume::Long(volume) => parse_long_volume(volume, project_name, project_volumes),
+        }
+    }
+}
+
+impl ProjectVolume {
+    /// The name docker knows the volume by. Volumes the project creates
+    /// without an explicit name are prefixed with the project's name, so
+    /// projects declaring the same volume don't share it.
+    pub(crate) fn docker_name(&self, project_name: &str, key: &str) -> String {
+        match (&self.name, &self.external) {
+            (Some(name), _) | (None, External::Named { name }) => name.clone(),
+            (None, External::Flag(true)) => key.to_string(),
+            (None, External::Flag(false)) => format!("{}_{}", project_name, key),
+        }
+    }
+
+    pub(crate) fn is_external(&self) -> bool {
+        !matches!(self.external, External::Flag(false))
+    }
+}
+
+fn parse_short_volume(
+    volume: &str,
+    project_name: &str,
+    project_volumes: &IndexMap<String, ProjectVolume>,
+) -> Result<VolumeMount, Box<dyn Error>> {
+    let parts = volume.split(':').collect::<Vec<_>>();
+    let (source, target, mode) = match parts[..] {
+        [target] => (None, target, None),
+        [source, target] => (Some(source), target, None),
+        [source, target, mode] => (Some(source), target, Some(mode)),
+        _ => return Err("expected [source:]target[:mode]".into()),
+    };
+    let modes = mode
+        .map(|m| m.split(',').collect::<Vec<_>>())
+        .unwrap_or_default();
+    let mut read_only = false;
+    let mut nocopy = false;
+    let mut relabel = None;
+    let mut propagation = None;
+    for mode in &modes {
+        match *mode {
+            "ro" => read_only = true,
+            "rw" => read_only = false,
+            "nocopy" => nocopy = true,
+            "z" | "Z" => relabel = Some(*mode),
+            "shared" | "rshared" | "slave" | "rslave" | "private" | "rprivate" => {
+                propagation = Some(*mode)
+            }
+            _ => return Err(format!("unknown volume mode {}", mode).into()),
+        }
+    }
+
+    let source = match source {
+        None => {
+            return Ok(VolumeMount::Mount(Box::new(Mount {
+                target: Some(target.to_string()),
+                typ: Some(MountTypeEnum::VOLUME),
+                read_only: Some(read_only),
+                ..Default::default()
+            })))
+        }
+        Some(source) => source,
+    };
+    if is_host_path(source) {
+        let source = resolve_host_path(source)?;
+        if relabel.is_some() {
+            return Ok(VolumeMount::Bind(format!(
+                "{}:{}:{}",
+                source,
+                target,
+                modes.join(",")
+            )));
+        }
+        return Ok(VolumeMount::Mount(Box::new(Mount {
+            target: Some(target.to_string()),
+            source: Some(source),
+            typ: Some(MountTypeEnum::BIND),
+            read_only: Some(read_only),
+            consistency: Some(String::from("default")),
+            bind_options: propagation.map(|propagation| MountBindOptions {
+                propagation: propagation.parse().ok(),
+                ..Default::default()
+            }),
+            ..Default::default()
+        })));
+    }
+    let project_volume = project_volumes
+        .get(source)
+        .ok_or_else(|| format!("named volume {} is not declared under volumes", source))?;
+    Ok(VolumeMount::Mount(Box::new(Mount {
+        target: Some(target.to_string()),
+        source: Some(project_volume.docker_name(project_name, source)),
+        typ: Some(MountTypeEnum::VOLUME),
+        read_only: Some(read_only),
+        volume_options: Some(MountVolumeOptions {
+            no_copy: Some(nocopy),
+            ..Default::default()
+        }),
+        ..Default::default()
+    })))
+}
+
+fn parse_long_volume(
+    volume: &LongVolume,
+    project_name: &str,
+    project_volumes: &IndexMap<String, ProjectVolume>,
+) -> Result<VolumeMount, Box<dyn Error>> {
+    let mut mount = Mount {
+        target: Some(volume.target.clone()),
+        read_only: Some(volume.read_only),
+        consistency: volume.consistency.clone(),
+        ..Default::default()
+    };
+    match volume.typ.as_str() {
+        "bind" => {
+            let source = volume
+                .source
+                .as_deref()
+                .ok_or("bind volumes need a source")?;
+            mount.typ = Some(MountTypeEnum::BIND);
+            mount.source = Some(resolve_host_path(source)?);
+            mount.bind_options = match &volume.bind {
+                Some(BindOptions {
+                    propagation: Some(propagation),
+                }) => Some(MountBindOptions {
+                    propagation: Some(
+                        propagation
+                            .parse::<MountBindOptionsPropagationEnum>()
+                            .map_err(|_| format!("unknown propagation {}", propagation))?,
+                    ),
+                    ..Default::default()
+                }),
+                _ => None,
+            };
+        }
+        "volume" => {
+            mount.typ = Some(MountTypeEnum::VOLUME);
+            if let Some(source) = &volume.source {
+                let project_volume = project_volumes.get(source).ok_or_else(|| {
+                    format!("named volume {} is not declared under volumes", source)
+                })?;
+                mount.source = Some(project_volume.docker_name(project_name, source));
+            }
+            mount.volume_options = volume.volume.as_ref().map(|options| MountVolumeOptions {
+                no_copy: Some(options.nocopy),
+                ..Default::default()
+            });
+        }
+        "tmpfs" => {
+            mount.typ = Some(MountTypeEnum::TMPFS);
+            mount.tmpfs_options = match &volume.tmpfs {
+                Some(TmpfsOptions { size, mode }) => Some(MountTmpfsOptions {
+                    size_bytes: size.as_ref().map(parse_byte_size).transpose()?,
+                    mode: *mode,
+                }),
+                None => None,
+            };
+        }
+        "npipe" => {
+            mount.typ = Some(MountTypeEnum::NPIPE);
+            mount.source = volume.source.clone();
+        }
+        typ => return Err(format!("unknown volume type {}", typ).into()),
+    }
+    Ok(VolumeMount::Mount(Box::new(mount)))
+}
+
+/// Splits the service `tmpfs` entries into the target path and mount
+/// options docker expects in `HostConfig::tmpfs`.
+pub(crate) fn tmpfs_mounts(tmpfs: Tmpfs) -> HashMap<String, String> {
+    let entries = match tmpfs {
+        Tmpfs::Simple(entry) => vec![entry],
+        Tmpfs::List(entries) => entries,
+    };
+    entries
+        .into_iter()
+        .map(|entry| match entry.split_once(':') {
+            Some((target, options)) => (target.to_string(), options.to_string()),
+            None => (entry, String::new()),
+        })
+        .collect()
+}
+
+fn is_host_path(source: &str) -> bool {
+    source.starts_with('.') || source.starts_with('/') || source.starts_with('~')
+}
+
+/// Makes a bind source absolute, resolving `~` against the home directory
+/// and relative paths against the project root.
+pub(crate) fn resolve_host_path(source: &str) -> Result<String, Box<dyn Error>> {
+    let path = if source == "~" || source.starts_with("~/") {
+        let home = env::var("HOME").map_err(|_| "cannot resolve ~ without HOME set")?;
+        PathBuf::from(home).join(source.trim_start_matches('~').trim_start_matches('/'))
+    } else {
+        env::current_dir()?.join(source)
+    };
+    // docker rejects sources with `.` or `..` components
+    let mut resolved = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                resolved.pop();
+            }
+            component => resolved.push(component),
+        }
+    }
+    Ok(resolved.to_str().unwrap().to_string())
+}
+
+fn parse_byte_size(size: &ByteSize) -> Result<i64, Box<dyn Error>> {
+    let text = match size {
+        ByteSize::Bytes(bytes) => return Ok(*bytes),
+        ByteSize::Text(text) => text.trim().to_lowercase(),
+    };
+    let digits = text
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(text.len());
+    let (amount, unit) = text.split_at(digits);
+    let amount: i64 = amount
+        .parse()
+        .map_err(|_| format!("invalid size {}", text))?;
+    let multiplier = match unit.trim_end_matches('b') {
+        "" => 1,
+        "k" => 1 << 10,
+        "m" => 1 << 20,
+        "g" => 1 << 30,
+        _ => return Err(format!("invalid size {}", text).into()),
+    };
+    Ok(amount * multiplier)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn project_volumes() -> IndexMap<String, ProjectVolume> {
+        let named = ProjectVolume {
+            name: Some("shared-data".to_string()),
+            ..Default::default()
+        };
+        IndexMap::from([
+            ("db".to_string(), ProjectVolume::default()),
+            ("data".to_string(), named),
+        ])
+    }
+
+    fn mount(volume: ServiceVolume) -> Mount {
+        match volume.to_mount("app", &project_volumes()).unwrap() {
+            VolumeMount::Mount(mount) => *mount,
+            VolumeMount::Bind(bind) => panic!("expected a mount, got bind {}", bind),
+        }
+    }
+
+    fn short(volume: &str) -> ServiceVolume {
+        ServiceVolume::Short(volume.to_string())
+    }
+
+    fn long(yaml: &str) -> ServiceVolume {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    fn in_project(path: &str) -> String {
+        env::current_dir()
+            .unwrap()
+            .join(path)
+            .to_str()
+            .unwrap()
+            .to_string()
+    }
+
+    #[test]
+    fn parses_anonymous_volumes() {
+        let mount = mount(short("/var/cache"));
+        assert_eq!(mount.typ, Some(MountTypeEnum::VOLUME));
+        assert_eq!(mount.source, None);
+        assert_eq!(mount.target.as_deref(), Some("/var/cache"));
+    }
+
+    #[test]
+    fn parses_named_volumes() {
+        let volume = mount(short("db:/var/lib/postgresql/data:ro,nocopy"));
+        assert_eq!(volume.typ, Some(MountTypeEnum::VOLUME));
+        assert_eq!(volume.source.as_deref(), Some("app_db"));
+        assert_eq!(volume.read_only, Some(true));
+        assert_eq!(volume.volume_options.unwrap().no_copy, Some(true));
+        let renamed = mount(short("data:/data"));
+        assert_eq!(renamed.source.as_deref(), Some("shared-data"));
+    }
+
+    #[test]
+    fn parses_bind_volumes() {
+        let mount = mount(short("./src/../app:/app:ro,rshared"));
+        assert_eq!(mount.typ, Some(MountTypeEnum::BIND));
+        assert_eq!(mount.source, Some(in_project("app")));
+        assert_eq!(mount.read_only, Some(true));
+        assert_eq!(
+            mount.bind_options.unwrap().propagation,
+            Some(MountBindOptionsPropagationEnum::RSHARED)
+        );
+    }
+
+    #[test]
+    fn relabeled_binds_use_the_bind_format() {
+        match short("./app:/app:z")
+            .to_mount("app", &project_volumes())
+            .unwrap()
+        {
+            VolumeMount::Bind(bind) => assert_eq!(bind, format!("{}:/app:z", in_project("app"))),
+            VolumeMount::Mount(mount) => panic!("expected a bind, got {:?}", mount),
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_short_volumes() {
+        let volumes = project_volumes();
+        assert!(short("undeclared:/data").to_mount("app", &volumes).is_err());
+        assert!(short("db:/data:fast").to_mount("app", &volumes).is_err());
+        assert!(short("a:b:c:d").to_mount("app", &volumes).is_err());
+    }
+
+    #[test]
+    fn parses_long_volumes() {
+        let bind = mount(long(
+            "type: bind\nsource: ./app\ntarget: /app\nread_only: true\nbind:\n  propagation: slave",
+        ));
+        assert_eq!(bind.typ, Some(MountTypeEnum::BIND));
+        assert_eq!(bind.source, Some(in_project("app")));
+        assert_eq!(bind.read_only, Some(true));
+        assert_eq!(
+            bind.bind_options.unwrap().propagation,
+            Some(MountBindOptionsPropagationEnum::SLAVE)
+        );
+
+        let volume = mount(long(
+            "type: volume\nsource: data\ntarget: /data\nvolume:\n  nocopy: true",
+        ));
+        assert_eq!(volume.source.as_deref(), Some("shared-data"));
+        assert_eq!(volume.volume_options.unwrap().no_copy, Some(true));
+
+        let tmpfs = mount(long(
+            "type: tmpfs\ntarget: /tmp\ntmpfs:\n  size: 64m\n  mode: 1777",
+        ));
+        assert_eq!(tmpfs.typ, Some(MountTypeEnum::TMPFS));
+        let options = tmpfs.tmpfs_options.unwrap();
+        assert_eq!(options.size_bytes, Some(64 << 20));
+        assert_eq!(options.mode, Some(1777));
+    }
+
+    #[test]
+    fn rejects_invalid_long_volumes() {
+        let volumes = project_volumes();
+        assert!(long("type: bind\ntarget: /app")
+            .to_mount("app", &volumes)
+            .is_err());
+        assert!(long("type: cluster\ntarget: /app")
+            .to_mount("app", &volumes)
+            .is_err());
+        assert!(long("type: tmpfs\ntarget: /tmp\ntmpfs:\n  size: 1x")
+            .to_mount("app", &volumes)
+            .is_err());
+    }
+
+    #[test]
+    fn splits_tmpfs_options() {
+        let mounts = tmpfs_mounts(Tmpfs::List(vec![
+            "/run".to_string(),
+            "/tmp:size=64m,mode=1777".to_string(),
+        ]));
+        assert_eq!(mounts.get("/run").map(String::as_str), Some(""));
+        assert_eq!(
+            mounts.get("/tmp").map(String::as_str),
+            Some("size=64m,mode=1777")
+        );
+    }
+
+    #[test]
+    fn reads_both_external_forms() {
+        let flag: ProjectVolume = serde_yaml::from_str("external: true").unwrap();
+        assert!(flag.is_external());
+        assert_eq!(flag.docker_name("app", "cache"), "cache");
+
+        let named: ProjectVolume = serde_yaml::from_str("external:\n  name: foo").unwrap();
+        assert!(named.is_external());
+        assert_eq!(named.docker_name("app", "cache"), "foo");
+
+        let project = ProjectVolume::default();
+        assert!(!project.is_external());
+        assert_eq!(project.docker_name("app", "cache"), "app_cache");
+    }
+}
@@ -1,9 +1,14 @@
 use self::{
     docker::{
-        compose::{compose_build, compose_down, compose_restart, compose_up, enter_shell},
+        compose::{
+            compose_build, compose_down, compose_exec, compose_logs, compose_pull, compose_restart,
+            compose_run, compose_up, enter_shell, set_compose_files, Recreate, RunConfig,
+        },
         docker_status,
+        logs::LogsConfig,
+        utils::ExecConfig,
     },
-    phoenix::{phoenix_cmd, phoenix_new, Phoenix},
+    phoenix::{iex, mix_cmd, phoenix_cmd, phoenix_new, Phoenix},
     rails::{rails_cmd, Rails},
 };
 use clap::{ArgEnum, Parser, Subcommand};
@@ -11,7 +16,7 @@ use clap_complete::{
     generate,
     shells::{Bash, PowerShell, Zsh},
 };
-use std::io;
+use std::{io, path::PathBuf};
 pub(crate) mod docker;
 mod greeting;
 mod phoenix;
@@ -25,6 +30,10 @@ pub(crate) struct Wizard {
     status: bool,
     #[clap(long, arg_enum)]
     generate_completion: Option<Shells>,
+    #[clap(short = 'f', long = "file", multiple_occurrences = true)]
+    /// Compose file to use instead of the one in the current directory.
+    /// Can be repeated, later files are merged into earlier ones
+    files: Vec<PathBuf>,
     #[clap(subcommand)]
     command: Option<Command>,
 }
@@ -42,6 +51,25 @@ enum Command {
     Rails(Rails),
     #[clap(subcommand)]
     Phoenix(Phoenix),
+    /// Run a mix task in the main project container
+    Mix {
+        #[clap(required = true, multiple_values = true, allow_hyphen_values = true)]
+        /// the mix task and its arguments
+        args: Vec<String>,
+    },
+    /// Open an iex shell for the phoenix project
+    Iex {
+        #[clap(long)]
+        /// connect to the phoenix node running in the app container instead
+        /// of starting a one-off container
+        remsh: bool,
+        #[clap(long, requires = "remsh")]
+        /// name of the node to connect to, defaults to the app's name
+        node: Option<String>,
+        #[clap(long, requires = "remsh")]
+        /// cookie of the node to connect to, defaults to the app's name
+        cookie: Option<String>,
+    },
     #[clap(flatten)]
     DockerCompose(DockerCompose),
     #[clap(flatten)]
@@ -58,64 +86,150 @@ enum WizardNew {
 }
 #[derive(Subcommand, Debug, Clone)]
 enum AppKind {
-    Rails {
-        #[clap(help = "The name of the new rails app")]
-        name: String,
-        #[clap(long, help = "Generate an api only app and skip view generation")]
-        api: bool,
-        #[clap(arg_enum, long, short, help = "Which database to use")]
-        database: Option<rails::Database>,
-    },
-    Phoenix {
-        #[clap(help = "The name of the new phoenix app")]
-        name: String,
-        // #[clap(long, help = "Generate an api only app and skip view generation")]
-        // api: bool,
-        // #[clap(arg_enum, long, short, help = "Which database to use")]
-        // database: Option<rails::Database>,
-    },
+    Rails(rails::NewRails),
+    Phoenix(phoenix::NewPhoenix),
     Rust,
 }
 #[derive(Subcommand, Debug)]
 enum DockerCompose {
     /// Start the docker compose project
     Start {
+        /// only start these services and the services they depend on
+        services: Vec<String>,
+        #[clap(long)]
+        /// don't start the services the given services depend on
+        no_deps: bool,
         #[clap(long, short)]
         /// start the project in detached mode
         detached: bool,
+        #[clap(long, default_value = "120")]
+        /// seconds to wait for dependencies to become healthy or complete
+        timeout: u64,
+        #[clap(long, conflicts_with = "no-recreate")]
+        /// recreate containers even if their configuration didn't change
+        force_recreate: bool,
+        #[clap(long)]
+        /// keep existing containers even if their configuration changed
+        no_recreate: bool,
     },
     /// Stop the docker compose project
-    Stop,
+    Stop {
+        /// only stop these services
+        services: Vec<String>,
+    },
     /// Restart the docker compose project
     /// This will stop and start the project
-    Restart,
+    Restart {
+        /// only restart these services
+        services: Vec<String>,
+    },
     /// Build the images in the docker compose project
     Build {
         #[clap(short, long)]
         /// force the build even if the images are up to date
         force: bool,
+        /// only build the images of these services
+        services: Vec<String>,
     },
-    /// Run a shell inside a container. If no container
-    /// is specified, the container for the first service
-    /// listed in the docker-compose file will be used
-    Shell {
+    /// Show the output of the project's containers
+    Logs {
+        /// only show the logs of these services
+        services: Vec<String>,
+        #[clap(short, long)]
+        /// keep streaming new output
+        follow: bool,
+        #[clap(long)]
+        /// number of lines to show from the end of the logs, or "all"
+        tail: Option<String>,
+        #[clap(long)]
+        /// show logs since a timestamp (e.g. 2022-03-01T13:23:37Z) or
+        /// relative duration (e.g. 42m)
+        since: Option<String>,
+        #[clap(long)]
+        /// show logs before a timestamp (e.g. 2022-03-01T13:23:37Z) or
+        /// relative duration (e.g. 42m)
+        until: Option<String>,
         #[clap(short, long)]
-        /// if provided, the shell will be run in the specified container
-        container_name: Option<String>,
+        /// show timestamps
+        timestamps: bool,
+        #[clap(long)]
+        /// don't color the container names
+        no_color: bool,
+    },
+    /// Run a command in a new container of a service
+    Run {
+        #[clap(long)]
+        /// remove the container once the command exits
+        rm: bool,
+        #[clap(short, long = "env", multiple_occurrences = true)]
+        /// set an environment variable (KEY=VALUE), can be repeated
+        env: Vec<String>,
+        #[clap(long)]
+        /// publish the service's ports to the host
+        service_ports: bool,
+        #[clap(long)]
+        /// build the service's image even if it exists already
+        build: bool,
+        /// the service to run the command for
+        service: String,
+        #[clap(multiple_values = true, allow_hyphen_values = true)]
+        /// the command to run instead of the service's command
+        command: Vec<String>,
+    },
+    /// Pull the images of the services that don't build their own
+    Pull {
+        /// only pull the images of these services
+        services: Vec<String>,
+    },
+    /// Run a shell inside a container. If no service is
+    /// specified, the service named after the project or
+    /// the first service listed in the docker-compose file
+    /// will be used
+    Shell {
+        #[clap(short, long, alias = "container-name", short_alias = 'c')]
+        /// if provided, the shell will be run in the container of this service
+        service: Option<String>,
         #[clap(short, long)]
         /// if provided, the shell will be run as the specified user
         user: Option<String>,
+        #[clap(long)]
+        /// key sequence detaching from the shell, defaults to ctrl-p,ctrl-q
+        detach_keys: Option<String>,
+    },
+    /// Run a command in the running container of a service
+    Exec {
+        #[clap(short, long)]
+        /// run the command as this user
+        user: Option<String>,
+        #[clap(short, long)]
+        /// run the command in this directory
+        workdir: Option<String>,
+        #[clap(short, long = "env", multiple_occurrences = true)]
+        /// set an environment variable (KEY=VALUE), can be repeated
+        env: Vec<String>,
+        #[clap(short = 'T')]
+        /// don't allocate a pseudo-TTY
+        no_tty: bool,
+        #[clap(long)]
+        /// key sequence detaching from the command, defaults to ctrl-p,ctrl-q
+        detach_keys: Option<String>,
+        /// the service to run the command in
+        service: String,
+        #[clap(required = true, multiple_values = true, allow_hyphen_values = true)]
+        /// the command to run
+        command: Vec<String>,
     },
 }
 #[tokio::main]
 pub(crate) async fn cli_client(args: Vec<String>) -> Result<(), Box<dyn std::error::Error>> {
     let wizard = Wizard::parse_from(&args);
+    set_compose_files(wizard.files.clone());
     if wizard.status {
         docker_status().await?;
     };
 
-    if wizard.generate_completion.is_some() {
-        match wizard.generate_completion.unwrap() {
+    if let Some(shell) = wizard.generate_completion {
+        match shell {
             Shells::Bash => generate(
                 Bash,
                 &mut <Wizard as clap::CommandFactory>::command(),
@@ -135,46 +249,110 @@ pub(crate) async fn cli_client(args: Vec<String>) -> Result<(), Box<dyn std::err
                 &mut io::stdout(),
             ),
         }
-        generate(
-            Bash,
-            &mut <Wizard as clap::CommandFactory>::command(),
-            "wizard".to_string(),
-            &mut io::stdout(),
-        );
     }
     if let Some(command) = wizard.command {
         match command {
-            Command::Rails(rails) => match rails {
-                Rails::Command(rails) => rails_cmd(rails).await?,
-            },
+            Command::Rails(rails) => rails_cmd(rails).await?,
             Command::DockerCompose(dc_opts) => match dc_opts {
-                DockerCompose::Start { detached } => {
-                    compose_up(detached).await?;
+                DockerCompose::Start {
+                    services,
+                    no_deps,
+                    detached,
+                    timeout,
+                    force_recreate,
+                    no_recreate,
+                } => {
+                    let recreate = match (force_recreate, no_recreate) {
+                        (true, _) => Recreate::Always,
+                        (_, true) => Recreate::Never,
+                        _ => Recreate::Changed,
+                    };
+                    compose_up(services, no_deps, detached, timeout, recreate).await?;
                 }
-                DockerCompose::Stop => {
-                    compose_down().await?;
+                DockerCompose::Stop { services } => {
+                    compose_down(services).await?;
+                }
+                DockerCompose::Restart { services } => compose_restart(services).await?,
+                DockerCompose::Build { force, services } => compose_build(force, services).await?,
+                DockerCompose::Pull { services } => compose_pull(services).await?,
+                DockerCompose::Run {
+                    rm,
+                    env,
+                    service_ports,
+                    build,
+                    service,
+                    command,
+                } => {
+                    let config = RunConfig {
+                        command,
+                        env,
+                        service_ports,
+                        remove: rm,
+                        build,
+                        ..Default::default()
+                    };
+                    compose_run(service, config).await?
+                }
+                DockerCompose::Logs {
+                    services,
+                    follow,
+                    tail,
+                    since,
+                    until,
+                    timestamps,
+                    no_color,
+                } => {
+                    let config = LogsConfig {
+                        follow,
+                        tail,
+                        since,
+                        until,
+                        timestamps,
+                        color: !no_color,
+                    };
+                    compose_logs(services, config).await?
                 }
-                DockerCompose::Restart => compose_restart().await,
-                DockerCompose::Build { force } => compose_build(force).await?,
                 DockerCompose::Shell {
-                    container_name,
+                    service,
+                    user,
+                    detach_keys,
+                } => enter_shell(service, user, detach_keys).await?,
+                DockerCompose::Exec {
                     user,
-                } => enter_shell(container_name, user).await?,
+                    workdir,
+                    env,
+                    no_tty,
+                    detach_keys,
+                    service,
+                    command,
+                } => {
+                    let command_args = command.iter().map(String::as_str).collect::<Vec<_>>();
+                    let config = ExecConfig {
+                        user: user.as_deref(),
+                        work_dir: workdir.as_deref(),
+                        command_args: &command_args,
+                        attach_stdin: Some(true),
+                        env: Some(env.iter().map(String::as_str).collect()),
+                        tty: no_tty.then_some(false),
+                        detach_keys: detach_keys.as_deref(),
+                    };
+                    compose_exec(Some(&service), config).await?
+                }
             },
             Command::New(app_kind) => match app_kind {
                 WizardNew::New { kind } => match kind {
-                    AppKind::Rails {
-                        name,
-                        api,
-                        database,
-                    } => rails::rails_new(name, api, database).await?,
-                    AppKind::Phoenix { name } => phoenix_new(name).await?,
+                    AppKind::Rails(new_rails) => rails::rails_new(new_rails).await?,
+                    AppKind::Phoenix(new_phoenix) => phoenix_new(new_phoenix).await?,
                     _ => println!("Not implemented"),
                 },
             },
-            Command::Phoenix(phoenix) => match phoenix {
-                Phoenix::Command(rails) => phoenix_cmd(rails).await?,
-            },
+            Command::Phoenix(phoenix) => phoenix_cmd(phoenix).await?,
+            Command::Mix { args } => mix_cmd(args).await?,
+            Command::Iex {
+                remsh,
+                node,
+                cookie,
+            } => iex(remsh, node, cookie).await?,
         }
     }
     Ok(())
@@ -1,11 +1,12 @@
 use super::docker::{
-    get_container_id, init_docker,
-    utils::{project_hash, remove_container, run_container_command, ExecConfig},
+    compose::{compose_exec, compose_exec_or_run, compose_run, RunConfig},
+    init_docker, resolve_service_name,
+    utils::{remove_container, run_container_command, ExecConfig},
 };
 use bollard::container::{Config, CreateContainerOptions};
 use bollard::image::CreateImageOptions;
 use bollard::models::HostConfig;
-use clap::{ArgEnum, Subcommand};
+use clap::{ArgEnum, Args, Subcommand};
 use crossterm::style::Stylize;
 use futures_util::TryStreamExt;
 use std::{env, error::Error, fs::File, io::Write};
@@ -13,6 +14,21 @@ use std::{env, error::Error, fs::File, io::Write};
 #[derive(Subcommand, Debug)]
 #[clap(about = "Execute a phoenix command in the main project container")]
 pub(crate) enum Phoenix {
+    /// Run the `ecto.*` task for a database action
+    Db {
+        #[clap(arg_enum)]
+        /// the database action to run
+        action: DbArgs,
+        #[clap(short, long)]
+        /// the MIX_ENV to run the task in
+        env: Option<String>,
+        #[clap(long)]
+        /// number of migrations to run or roll back, for migrate and rollback
+        step: Option<u32>,
+        #[clap(long)]
+        /// version to migrate or roll back to, for migrate and rollback
+        version: Option<String>,
+    },
     #[clap(external_subcommand)]
     Command(Vec<String>),
 }
@@ -28,34 +44,308 @@ pub(crate) enum DbArgs {
     Prepare,
 }
 
+impl DbArgs {
+    fn tasks(&self) -> &'static [&'static str] {
+        match self {
+            DbArgs::Create => &["ecto.create"],
+            DbArgs::Drop => &["ecto.drop"],
+            DbArgs::Migrate => &["ecto.migrate"],
+            DbArgs::Reset => &["ecto.reset"],
+            DbArgs::Rollback => &["ecto.rollback"],
+            DbArgs::Seed => &["run", "priv/repo/seeds.exs"],
+            DbArgs::Setup => &["ecto.setup"],
+            // ecto has no prepare task, creating is a no-op for an existing
+            // database
+            DbArgs::Prepare => &["do", "ecto.create,", "ecto.migrate"],
+        }
+    }
+}
+
 #[derive(Debug, ArgEnum, Clone)]
 pub(crate) enum Database {
-    Postgresql,
+    Postgres,
     Mysql,
     Sqlite3,
+    Mssql,
 }
 
-pub(crate) async fn phoenix_cmd(args: Vec<String>) -> Result<(), Box<dyn Error>> {
-    let docker = init_docker();
+#[derive(Args, Debug, Clone)]
+pub(crate) struct NewPhoenix {
+    #[clap(help = "The name of the new phoenix app")]
+    name: String,
+    #[clap(arg_enum, long, short, default_value = "postgres")]
+    /// which database to use
+    database: Database,
+    #[clap(long)]
+    /// don't generate ecto files, which also skips the database
+    no_ecto: bool,
+    #[clap(long)]
+    /// don't generate html views
+    no_html: bool,
+    #[clap(long)]
+    /// don't generate the asset pipeline
+    no_assets: bool,
+    #[clap(long)]
+    /// use binary ids in the generated schemas
+    binary_id: bool,
+    #[clap(long, requires = "phoenix-version")]
+    /// generate a LiveView based app. Only for phx_new below 1.6, where
+    /// LiveView became the default and the flag was removed
+    live: bool,
+    #[clap(long)]
+    /// phx_new version to generate the app with, the latest when not given
+    phoenix_version: Option<String>,
+}
 
-    let path = env::current_dir()?;
-    let project_hash = project_hash(path.to_str().unwrap());
-    let project_name = path.file_name().unwrap().to_str().unwrap().to_owned();
-    let container_name = format!("{}-{}-{}", project_name, project_name, project_hash);
-    let user = format!("{}-user", project_name);
-    let id = get_container_id(&docker, &container_name).await?;
-    let cmd = args.iter().map(|s| s.as_str()).collect::<Vec<&str>>();
+/// The compose service, `.env` file and `DATABASE_URL` of the database a
+/// new phoenix app uses. The credentials in `.env` are read by both the
+/// database and the app.
+fn database_service(database: &Database, name: &str) -> Option<(&'static str, String)> {
+    let database_name = format!("{}_dev", name);
+    match database {
+        Database::Postgres => Some((
+            r#"
+  db:
+      image: postgres:latest
+      volumes:
+          - db-data:/var/lib/postgresql/data
+      ports:
+          - 5432:5432
+      env_file:
+          - .env
+      healthcheck:
+          test: ["CMD-SHELL", "pg_isready -U postgres"]
+          interval: 5s
+          timeout: 5s
+          retries: 10
+volumes:
+  db-data:"#,
+            format!(
+                "POSTGRES_USER=postgres\nPOSTGRES_PASSWORD=postgres\nDATABASE_URL=ecto://postgres:postgres@db:5432/{}\n",
+                database_name
+            ),
+        )),
+        Database::Mysql => Some((
+            r#"
+  db:
+      image: mysql:latest
+      command:
+          - --default-authentication-plugin=mysql_native_password
+      volumes:
+          - mysql:/var/lib/mysql
+      ports:
+          - "3306:3306"
+      env_file:
+          - .env
+      healthcheck:
+          test: ["CMD", "mysqladmin", "ping", "-h", "localhost", "-proot"]
+          interval: 5s
+          timeout: 5s
+          retries: 10
+volumes:
+  mysql:"#,
+            format!(
+                "MYSQL_ROOT_PASSWORD=root\nDATABASE_URL=ecto://root:root@db:3306/{}\n",
+                database_name
+            ),
+        )),
+        Database::Mssql => Some((
+            r#"
+  db:
+      image: mcr.microsoft.com/mssql/server:2019-latest
+      volumes:
+          - mssql:/var/opt/mssql
+      ports:
+          - "1433:1433"
+      env_file:
+          - .env
+      healthcheck:
+          test: /opt/mssql-tools/bin/sqlcmd -S localhost -U sa -P "$$MSSQL_SA_PASSWORD" -Q "SELECT 1"
+          interval: 5s
+          timeout: 5s
+          retries: 10
+volumes:
+  mssql:"#,
+            format!(
+                "ACCEPT_EULA=Y\nMSSQL_SA_PASSWORD=Wizard_passw0rd\nDATABASE_URL=ecto://sa:Wizard_passw0rd@db:1433/{}\n",
+                database_name
+            ),
+        )),
+        // sqlite keeps the database in a file inside the project
+        Database::Sqlite3 => None,
+    }
+}
+
+/// Lets `config/dev.exs` take the repo's connection from `DATABASE_URL`,
+/// which ecto prefers over the hostname and credentials set there.
+fn dev_database_url_config(name: &str) -> String {
+    let module = name
+        .split('_')
+        .map(|part| {
+            let mut chars = part.chars();
+            chars
+                .next()
+                .map(|first| first.to_uppercase().chain(chars).collect::<String>())
+                .unwrap_or_default()
+        })
+        .collect::<String>();
+    format!(
+        r#"
+
+# The database runs in the db service of docker-compose.yml
+if database_url = System.get_env("DATABASE_URL") do
+  config :{}, {}.Repo, url: database_url
+end
+"#,
+        name, module
+    )
+}
+
+pub(crate) async fn phoenix_cmd(phoenix: Phoenix) -> Result<(), Box<dyn Error>> {
+    match phoenix {
+        Phoenix::Db {
+            action,
+            env,
+            step,
+            version,
+        } => phoenix_db(action, env, step, version).await,
+        Phoenix::Command(args) => phoenix_exec(args, None).await,
+    }
+}
+
+/// Runs `mix` with `args` in the app container.
+pub(crate) async fn mix_cmd(args: Vec<String>) -> Result<(), Box<dyn Error>> {
+    phoenix_exec([vec!["mix".to_string()], args].concat(), None).await
+}
+
+async fn phoenix_db(
+    action: DbArgs,
+    mix_env: Option<String>,
+    step: Option<u32>,
+    version: Option<String>,
+) -> Result<(), Box<dyn Error>> {
+    let migrates = matches!(action, DbArgs::Migrate | DbArgs::Rollback);
+    if !migrates && (step.is_some() || version.is_some()) {
+        return Err("--step and --version can only be used with migrate and rollback".into());
+    }
+    let mut args = vec!["mix".to_string()];
+    args.extend(action.tasks().iter().map(|arg| arg.to_string()));
+    if let Some(step) = step {
+        args.extend(["--step".to_string(), step.to_string()]);
+    }
+    if let Some(version) = version {
+        args.extend(["--to".to_string(), version]);
+    }
+    phoenix_exec(args, mix_env).await
+}
+
+/// Opens `iex -S mix` in a one-off container of the app service, or with
+/// `remsh` a remote shell to the phoenix node running in the app container.
+/// The node and cookie default to the app's name, which generated apps
+/// start their node with.
+pub(crate) async fn iex(
+    remsh: bool,
+    node: Option<String>,
+    cookie: Option<String>,
+) -> Result<(), Box<dyn Error>> {
+    let service_name = resolve_service_name(None)?;
+    let user = format!("{}-user", project_name()?);
+    if !remsh {
+        let command = ["iex", "-S", "mix"].map(String::from).to_vec();
+        let config = RunConfig {
+            command,
+            remove: true,
+            user: Some(user),
+            ..Default::default()
+        };
+        return compose_run(service_name, config).await;
+    }
+    let node = node.unwrap_or_else(|| service_name.clone());
+    let cookie = cookie.unwrap_or_else(|| service_name.clone());
+    let own_name = format!("remsh_{}", std::process::id());
+    let run_command = ExecConfig {
+        user: Some(&user),
+        command_args: &[
+            "iex", "--sname", &own_name, "--cookie", &cookie, "--remsh", &node,
+        ],
+        attach_stdin: Some(true),
+        ..Default::default()
+    };
+    compose_exec(Some(&service_name), run_command).await
+}
+
+/// Runs `command` in the app container, as the project user and in
+/// `mix_env` when given, or in a one-off container when it isn't running.
+async fn phoenix_exec(command: Vec<String>, mix_env: Option<String>) -> Result<(), Box<dyn Error>> {
+    let user = format!("{}-user", project_name()?);
+    let mix_env = mix_env.map(|mix_env| format!("MIX_ENV={}", mix_env));
+    let cmd = command.iter().map(|s| s.as_str()).collect::<Vec<&str>>();
     let run_command = ExecConfig {
         user: Some(&user),
         command_args: &cmd,
         attach_stdin: Some(true),
+        env: mix_env.as_deref().map(|mix_env| vec![mix_env]),
         ..Default::default()
     };
-    run_container_command(&docker, &id, run_command).await?;
-    Ok(())
+    compose_exec_or_run(run_command).await
+}
+
+/// Whether phx_new `version` still has the `--live` flag, which 1.6 removed.
+fn before_live_default(version: &str) -> bool {
+    let mut parts = version.split('.').map(|part| part.parse::<u32>().ok());
+    match (parts.next().flatten(), parts.next().flatten()) {
+        (Some(major), Some(minor)) => (major, minor) < (1, 6),
+        (Some(major), None) => major < 1,
+        _ => false,
+    }
+}
+
+fn project_name() -> Result<String, Box<dyn Error>> {
+    let path = env::current_dir()?;
+    Ok(path.file_name().unwrap().to_str().unwrap().to_owned())
 }
 
-pub(crate) async fn phoenix_new(name: String) -> Result<(), Box<dyn Error>> {
+pub(crate) async fn phoenix_new(new_phoenix: NewPhoenix) -> Result<(), Box<dyn Error>> {
+    let NewPhoenix {
+        name,
+        database,
+        no_ecto,
+        no_html,
+        no_assets,
+        binary_id,
+        live,
+        phoenix_version,
+    } = new_phoenix;
+    if live && !phoenix_version.as_deref().is_some_and(before_live_default) {
+        return Err("--live only exists for --phoenix-version below 1.6".into());
+    }
+    let mut new_args = vec![
+        "mix".to_string(),
+        "phx.new".to_string(),
+        name.clone(),
+        "--install".to_string(),
+    ];
+    if no_ecto {
+        new_args.push("--no-ecto".to_string());
+    } else {
+        new_args.push(format!(
+            "--database={}",
+            database.to_possible_value().unwrap().get_name()
+        ));
+    }
+    for (set, flag) in [
+        (no_html, "--no-html"),
+        (no_assets, "--no-assets"),
+        (binary_id, "--binary-id"),
+        (live, "--live"),
+    ] {
+        if set {
+            new_args.push(flag.to_string());
+        }
+    }
+    let database_service = (!no_ecto)
+        .then(|| database_service(&database, &name))
+        .flatten();
     let user = whoami::username();
     let path = env::current_dir()?;
     let docker = init_docker();
@@ -92,21 +382,26 @@ services:
       ports:
           - '4000:4000'
       env_file:
-          - .env
-      command: mix phx.server
-  db:
-      image: postgres:latest
-      volumes:
-          - db-data:/var/lib/postgresql/data
-      ports:
-          - 5432:5432
-      environment:
-          POSTGRES_USER: postgres
-          POSTGRES_PASSWORD: postgres
-volumes:
-  db-data:"#,
-        &name
+          - .env{1}
+      command: elixir --sname {0} --cookie {0} -S mix phx.server{2}"#,
+        &name,
+        if database_service.is_some() {
+            r#"
+      depends_on:
+          db:
+              condition: service_healthy"#
+        } else {
+            ""
+        },
+        database_service
+            .as_ref()
+            .map(|(service, _)| *service)
+            .unwrap_or_default()
     );
+    let env_file = database_service
+        .as_ref()
+        .map(|(_, env_file)| env_file.clone())
+        .unwrap_or_default();
 
     let docker_compose_file = ("docker-compose.yml", docker_compose_file);
 
@@ -165,16 +460,19 @@ volumes:
         ..Default::default()
     };
 
+    let mut install_phoenix_args = vec!["mix", "archive.install", "hex", "phx_new"];
+    install_phoenix_args.extend(phoenix_version.as_deref());
+    install_phoenix_args.push("--force");
     let install_phoenix = ExecConfig {
         user: Some(&user),
-        command_args: &["mix", "archive.install", "hex", "phx_new", "--force"],
+        command_args: &install_phoenix_args,
         ..Default::default()
     };
 
     let create_app = ExecConfig {
         user: Some(&user),
         work_dir: Some(&work_dir),
-        command_args: &["mix", "phx.new", &name, "--install"],
+        command_args: &new_args.iter().map(String::as_str).collect::<Vec<_>>(),
         attach_stdin: Some(true),
         ..Default::default()
     };
@@ -186,9 +484,12 @@ volumes:
         install_phoenix,
         create_app,
     ] {
-        let run_result = run_container_command(&docker, &id, execution.clone()).await;
-        if run_result.is_err() {
-            let err_message = run_result.unwrap_err().to_string();
+        let failure = match run_container_command(&docker, &id, execution.clone()).await {
+            Ok(result) if result.success() => None,
+            Ok(result) => Some(result.last_line()),
+            Err(err) => Some(err.to_string()),
+        };
+        if let Some(err_message) = failure {
             remove_container(&id).await;
             println!(
                 "\n[{}] - failed to execute command: {}",
@@ -203,16 +504,15 @@ volumes:
             std::process::exit(1);
         }
     }
-    for config_file in [docker_file, docker_compose_file, (".env", "".to_string())] {
+    for config_file in [docker_file, docker_compose_file, (".env", env_file)] {
         if let Ok(mut file) = File::create(format!("{}/{}", &name, &config_file.0)) {
-            let file_result = file.write_all(config_file.1.as_bytes());
-            if file_result.is_err() {
+            if let Err(err) = file.write_all(config_file.1.as_bytes()) {
                 remove_container(&id).await;
                 println!(
                     "[{}] - Could not write to file: {} - {}",
                     "error".dark_red(),
                     config_file.0.cyan(),
-                    file_result.unwrap_err().to_string().red(),
+                    err.to_string().red(),
                 );
                 std::process::exit(1);
             }
@@ -230,6 +530,24 @@ volumes:
         }
     }
 
+    if database_service.is_some() {
+        let dev_config = format!("{}/config/dev.exs", &name);
+        let appended = std::fs::OpenOptions::new()
+            .append(true)
+            .open(&dev_config)
+            .and_then(|mut file| file.write_all(dev_database_url_config(&name).as_bytes()));
+        if let Err(err) = appended {
+            remove_container(&id).await;
+            println!(
+                "[{}] - Could not write to file: {} - {}",
+                "error".dark_red(),
+                dev_config.cyan(),
+                err.to_string().red(),
+            );
+            std::process::exit(1);
+        }
+    }
+
     remove_container(&id).await;
     Ok(())
 }
@@ -1,6 +1,7 @@
 use super::docker::{
-    get_container_id, init_docker,
-    utils::{project_hash, remove_container, run_container_command, ExecConfig},
+    compose::compose_exec_or_run,
+    init_docker,
+    utils::{remove_container, run_container_command, ExecConfig},
 };
 use bollard::models::HostConfig;
 use bollard::{
@@ -8,7 +9,7 @@ use bollard::{
     models::Mount,
 };
 use bollard::{image::CreateImageOptions, models::MountTypeEnum};
-use clap::{ArgEnum, Subcommand};
+use clap::{ArgEnum, Args, Subcommand};
 use crossterm::style::Stylize;
 use futures_util::TryStreamExt;
 use std::{env, error::Error, fs::File, io::Write};
@@ -16,6 +17,65 @@ use std::{env, error::Error, fs::File, io::Write};
 #[derive(Subcommand, Debug)]
 #[clap(about = "Execute a rails command in the main project container")]
 pub(crate) enum Rails {
+    /// Run a `rails db:*` task
+    Db {
+        #[clap(arg_enum)]
+        /// the database task to run
+        action: DbArgs,
+        #[clap(short, long)]
+        /// the RAILS_ENV to run the task in
+        env: Option<String>,
+        #[clap(long)]
+        /// number of migrations to roll back, only for rollback
+        step: Option<u32>,
+        #[clap(long)]
+        /// version to migrate to, only for migrate
+        version: Option<String>,
+    },
+    /// Open a rails console
+    Console {
+        #[clap(short, long)]
+        /// the RAILS_ENV to run the console in
+        env: Option<String>,
+    },
+    /// Run the test suite with `rails test`
+    Test {
+        #[clap(short, long)]
+        /// the RAILS_ENV to run the tests in
+        env: Option<String>,
+        #[clap(multiple_values = true, allow_hyphen_values = true)]
+        /// test files and options passed on to `rails test`
+        args: Vec<String>,
+    },
+    /// Run the test suite with `rspec`
+    Rspec {
+        #[clap(long)]
+        /// the RAILS_ENV to run the specs in
+        env: Option<String>,
+        #[clap(multiple_values = true, allow_hyphen_values = true)]
+        /// spec files and options passed on to `rspec`
+        args: Vec<String>,
+    },
+    /// Run a rails generator
+    Generate {
+        #[clap(short, long)]
+        /// the RAILS_ENV to run the generator in
+        env: Option<String>,
+        /// the generator to run, such as model or controller
+        generator: String,
+        #[clap(multiple_values = true, allow_hyphen_values = true)]
+        /// arguments passed on to the generator
+        args: Vec<String>,
+    },
+    /// List the app's routes
+    Routes {
+        #[clap(short, long)]
+        /// the RAILS_ENV to list the routes of
+        env: Option<String>,
+        #[clap(multiple_values = true, allow_hyphen_values = true)]
+        /// options passed on to `rails routes`, such as `-g`
+        args: Vec<String>,
+    },
     #[clap(external_subcommand)]
     Command(Vec<String>),
 }
@@ -31,6 +91,21 @@ pub(crate) enum DbArgs {
     Prepare,
 }
 
+impl DbArgs {
+    fn task(&self) -> &'static str {
+        match self {
+            DbArgs::Create => "db:create",
+            DbArgs::Drop => "db:drop",
+            DbArgs::Migrate => "db:migrate",
+            DbArgs::Reset => "db:reset",
+            DbArgs::Rollback => "db:rollback",
+            DbArgs::Seed => "db:seed",
+            DbArgs::Setup => "db:setup",
+            DbArgs::Prepare => "db:prepare",
+        }
+    }
+}
+
 #[derive(Debug, ArgEnum, Clone)]
 pub(crate) enum Database {
     Postgresql,
@@ -38,47 +113,165 @@ pub(crate) enum Database {
     Sqlite3,
 }
 
-pub(crate) async fn rails_cmd(args: Vec<String>) -> Result<(), Box<dyn Error>> {
-    let docker = init_docker();
+#[derive(Debug, ArgEnum, Clone)]
+pub(crate) enum Css {
+    Tailwind,
+    Bootstrap,
+    Bulma,
+    Postcss,
+    Sass,
+}
 
+#[derive(Debug, ArgEnum, Clone)]
+pub(crate) enum Javascript {
+    Importmap,
+    Webpack,
+    Esbuild,
+    Rollup,
+}
+
+#[derive(Args, Debug, Clone)]
+pub(crate) struct NewRails {
+    #[clap(help = "The name of the new rails app")]
+    name: String,
+    #[clap(long, help = "Generate an api only app and skip view generation")]
+    api: bool,
+    #[clap(arg_enum, long, short, help = "Which database to use")]
+    database: Option<Database>,
+    #[clap(long, default_value = "3.1")]
+    /// ruby version of the image the app is generated in and runs on
+    ruby_version: String,
+    #[clap(long)]
+    /// rails version to generate the app with, the latest when not given
+    rails_version: Option<String>,
+    #[clap(arg_enum, long)]
+    /// css processor to use
+    css: Option<Css>,
+    #[clap(arg_enum, long, short)]
+    /// javascript approach to use
+    javascript: Option<Javascript>,
+    #[clap(long)]
+    /// don't generate test files
+    skip_test: bool,
+    #[clap(long)]
+    /// generate a minimal app
+    minimal: bool,
+    #[clap(long, short)]
+    /// application template to apply, a file or a URL
+    template: Option<String>,
+    #[clap(long)]
+    /// run `rails db:prepare` whenever the app starts, which creates the
+    /// database on the first `wizard start`
+    prepare_db: bool,
+}
+
+/// Where a template file given to `wizard new rails` is mounted in the
+/// container generating the app.
+const TEMPLATE_PATH: &str = "/rails-template.rb";
+
+pub(crate) async fn rails_cmd(rails: Rails) -> Result<(), Box<dyn Error>> {
+    let rails_command = |task: &str, args: Vec<String>| {
+        [vec!["rails".to_string(), task.to_string()], args].concat()
+    };
+    match rails {
+        Rails::Db {
+            action,
+            env,
+            step,
+            version,
+        } => rails_db(action, env, step, version).await,
+        Rails::Console { env } => rails_exec(rails_command("console", vec![]), env).await,
+        Rails::Test { env, args } => rails_exec(rails_command("test", args), env).await,
+        Rails::Rspec { env, args } => {
+            let command = [
+                vec![
+                    "bundle".to_string(),
+                    "exec".to_string(),
+                    "rspec".to_string(),
+                ],
+                args,
+            ];
+            rails_exec(command.concat(), env).await
+        }
+        Rails::Generate {
+            env,
+            generator,
+            args,
+        } => {
+            rails_exec(
+                rails_command("generate", [vec![generator], args].concat()),
+                env,
+            )
+            .await
+        }
+        Rails::Routes { env, args } => rails_exec(rails_command("routes", args), env).await,
+        Rails::Command(args) => rails_exec([vec!["rails".to_string()], args].concat(), None).await,
+    }
+}
+
+async fn rails_db(
+    action: DbArgs,
+    rails_env: Option<String>,
+    step: Option<u32>,
+    version: Option<String>,
+) -> Result<(), Box<dyn Error>> {
+    if step.is_some() && !matches!(action, DbArgs::Rollback) {
+        return Err("--step can only be used with rollback".into());
+    }
+    if version.is_some() && !matches!(action, DbArgs::Migrate) {
+        return Err("--version can only be used with migrate".into());
+    }
+    let mut args = vec![action.task().to_string()];
+    if let Some(step) = step {
+        args.push(format!("STEP={}", step));
+    }
+    if let Some(version) = version {
+        args.push(format!("VERSION={}", version));
+    }
+    rails_exec([vec!["rails".to_string()], args].concat(), rails_env).await
+}
+
+/// Runs `command` in the app container, as the project user and in
+/// `rails_env` when given. When the app container isn't running, the
+/// command runs in a one-off container of the app service instead.
+async fn rails_exec(command: Vec<String>, rails_env: Option<String>) -> Result<(), Box<dyn Error>> {
     let path = env::current_dir()?;
-    let project_hash = project_hash(path.to_str().unwrap());
     let project_name = path.file_name().unwrap().to_str().unwrap().to_owned();
-    let container_name = format!("{}-{}-{}", project_name, project_name, project_hash);
     let user = format!("{}-user", project_name);
+    let rails_env = rails_env.map(|rails_env| format!("RAILS_ENV={}", rails_env));
 
-    let id = get_container_id(&docker, &container_name).await?;
-    let mut cmd = vec!["rails".to_string()];
-    cmd.append(&mut args.clone());
-    let cmd = cmd.iter().map(|s| s.as_str()).collect::<Vec<&str>>();
+    let cmd = command.iter().map(|s| s.as_str()).collect::<Vec<&str>>();
     let run_command = ExecConfig {
         user: Some(&user),
         command_args: &cmd,
         attach_stdin: Some(true),
+        env: rails_env.as_deref().map(|rails_env| vec![rails_env]),
         ..Default::default()
     };
-    run_container_command(&docker, &id, run_command).await?;
-    Ok(())
+    compose_exec_or_run(run_command).await
 }
 
-pub(crate) async fn rails_new(
-    name: String,
-    api: bool,
-    database: Option<Database>,
-) -> Result<(), Box<dyn Error>> {
+pub(crate) async fn rails_new(new_rails: NewRails) -> Result<(), Box<dyn Error>> {
+    let NewRails {
+        name,
+        api,
+        database,
+        ruby_version,
+        rails_version,
+        css,
+        javascript,
+        skip_test,
+        minimal,
+        template,
+        prepare_db,
+    } = new_rails;
     // let user = whoami::username();
     let user = format!("{}-user", &name);
     let path = env::current_dir()?;
-    let api = if api { "--api" } else { "" };
-    let db = if database.is_some() {
+    let db = if let Some(database) = &database {
         format!(
             "--database={:?}",
-            database
-                .clone()
-                .unwrap()
-                .to_possible_value()
-                .unwrap()
-                .get_name()
+            database.to_possible_value().unwrap().get_name()
         )
         .replace("\"", "")
     } else {
@@ -88,12 +281,48 @@ pub(crate) async fn rails_new(
         Some(Database::Sqlite3) => "RUN apt-get update && apt-get install -y sqlite3".to_owned(),
         _ => "".to_owned(),
     };
+    let mut new_args = vec![db];
+    if api {
+        new_args.push("--api".to_string());
+    }
+    if let Some(css) = &css {
+        new_args.push(format!(
+            "--css={}",
+            css.to_possible_value().unwrap().get_name()
+        ));
+    }
+    if let Some(javascript) = &javascript {
+        new_args.push(format!(
+            "--javascript={}",
+            javascript.to_possible_value().unwrap().get_name()
+        ));
+    }
+    if skip_test {
+        new_args.push("--skip-test".to_string());
+    }
+    if minimal {
+        new_args.push("--minimal".to_string());
+    }
+    // template files are mounted into the container, URLs are fetched by rails
+    let template_file = match &template {
+        Some(template) if template.starts_with("http://") || template.starts_with("https://") => {
+            new_args.push(format!("--template={}", template));
+            None
+        }
+        Some(template) => {
+            let template_file = std::fs::canonicalize(template)
+                .map_err(|err| format!("Could not find template {}: {}", template, err))?;
+            new_args.push(format!("--template={}", TEMPLATE_PATH));
+            Some(template_file)
+        }
+        None => None,
+    };
     let docker = init_docker();
-    const IMAGE: &str = "ruby:3.1";
+    let image = format!("ruby:{}", ruby_version);
     let docker_file: (&str, String) = (
         "Dockerfile",
         format!(
-            r#"FROM ruby:3.1
+            r#"FROM {2}
 
 WORKDIR /app
 RUN groupadd -r {0} -g 1000
@@ -108,69 +337,90 @@ RUN bundle install
 
 COPY . /app
 CMD ["tail", "-f", "/dev/null"]"#,
-            &user, install_sqlite3
+            &user, install_sqlite3, &image
         ),
     );
-    let docker_compose_file: String = format!(
-        r#"version: '3.6'
-services:
-  {0}:
-      build:
-          context: .
-      volumes:
-          - .:/app
-      ports:
-          - '3000:3000'
-      env_file:
-          - .env
-      command: bash -c "rm -f tmp/pids/server.pid && bundle exec rails s -p 3000 -b '0.0.0.0'""#,
-        &name
-    );
-    let docker_compose_file = if database.is_some() {
-        match database.unwrap() {
-            Database::Postgresql => format!(
-                r#"{}
+    // the app reaches the database through DATABASE_URL, which rails merges
+    // into config/database.yml. It has no database path, so every RAILS_ENV
+    // keeps its own database. The db service reads its credentials
+    // from the same .env file
+    let (db_service, env_file) = match database {
+        Some(Database::Postgresql) => (
+            r#"
   db:
       image: postgres:latest
       volumes:
           - db-data:/var/lib/postgresql/data
       ports:
           - 5432:5432
-      environment:
-          POSTGRES_USER: postgres
-          POSTGRES_PASSWORD: postgres
+      env_file:
+          - .env
+      healthcheck:
+          test: ["CMD-SHELL", "pg_isready -U postgres"]
+          interval: 5s
+          timeout: 5s
+          retries: 10
 volumes:
   db-data:"#,
-                docker_compose_file
-            ),
-            Database::Mysql => format!(
-                r#"{}
+            "POSTGRES_USER=postgres\nPOSTGRES_PASSWORD=postgres\nDATABASE_URL=postgres://postgres:postgres@db:5432\n".to_string(),
+        ),
+        Some(Database::Mysql) => (
+            r#"
   db:
       image: mysql:latest
       command:
           - --default-authentication-plugin=mysql_native_password
-      environment:
-          - MYSQL_ROOT_PASSWORD=root
-          - MYSQL_DATABASE=app_development
+      env_file:
+          - .env
       ports:
           - "3306:3306"
       volumes:
           - mysql:/var/lib/mysql
+      healthcheck:
+          test: ["CMD", "mysqladmin", "ping", "-h", "localhost", "-proot"]
+          interval: 5s
+          timeout: 5s
+          retries: 10
 volumes:
   mysql:"#,
-                docker_compose_file
-            ),
-            Database::Sqlite3 => docker_compose_file,
-        }
+            "MYSQL_ROOT_PASSWORD=root\nDATABASE_URL=mysql2://root:root@db:3306\n".to_string(),
+        ),
+        _ => ("", String::new()),
+    };
+    let depends_on = if db_service.is_empty() {
+        ""
+    } else {
+        r#"
+      depends_on:
+          db:
+              condition: service_healthy"#
+    };
+    let prepare_db = if prepare_db {
+        "bundle exec rails db:prepare && "
     } else {
-        docker_compose_file
+        ""
     };
+    let docker_compose_file: String = format!(
+        r#"version: '3.6'
+services:
+  {0}:
+      build:
+          context: .
+      volumes:
+          - .:/app
+      ports:
+          - '3000:3000'
+      env_file:
+          - .env{1}
+      command: bash -c "rm -f tmp/pids/server.pid && {2}bundle exec rails s -p 3000 -b '0.0.0.0'"{3}"#,
+        &name, depends_on, prepare_db, db_service
+    );
     let docker_compose_file = ("docker-compose.yml", docker_compose_file);
 
     docker
         .create_image(
             Some(CreateImageOptions {
-                from_image: IMAGE,
+                from_image: image.as_str(),
                 ..Default::default()
             }),
             None,
@@ -179,12 +429,16 @@ volumes:
         .try_collect::<Vec<_>>()
         .await?;
     let work_dir = format!("/home/{}", &user);
+    let mut binds = vec![format!("{}:{}:rw", &path.to_str().unwrap(), &work_dir)];
+    if let Some(template_file) = &template_file {
+        binds.push(format!(
+            "{}:{}:ro",
+            template_file.to_str().unwrap(),
+            TEMPLATE_PATH
+        ));
+    }
     let host_config = HostConfig {
-        binds: Some(vec![format!(
-            "{}:{}:rw",
-            &path.to_str().unwrap(),
-            &work_dir
-        )]),
+        binds: Some(binds),
         mounts: Some(vec![Mount {
             target: Some("/tmp".to_string()),
             typ: Some(MountTypeEnum::TMPFS),
@@ -195,7 +449,7 @@ volumes:
     };
 
     let ruby_config = Config {
-        image: Some(IMAGE),
+        image: Some(image.as_str()),
         tty: Some(true),
         working_dir: Some(&work_dir),
         host_config: Some(host_config),
@@ -222,23 +476,36 @@ volumes:
         ..Default::default()
     };
 
+    let mut install_rails_args = vec![
+        "gem",
+        "install",
+        "rails",
+        "--no-document",
+        "--no-user-install",
+    ];
+    // `rails _<version>_` picks the installed version to run
+    let rails_executable_version = rails_version
+        .as_ref()
+        .map(|version| format!("_{}_", version));
+    if let Some(version) = &rails_version {
+        install_rails_args.extend(["-v", version]);
+    }
+    let mut create_app_args = vec!["rails"];
+    create_app_args.extend(rails_executable_version.as_deref());
+    create_app_args.extend(["new", &name]);
+    create_app_args.extend(new_args.iter().map(String::as_str));
+
     let install_rails = ExecConfig {
         user: Some(&user),
         env: Some(vec!["GEM_PATH=/tmp/gem", "GEM_SPEC_CACHE=/tmp/gem/cache"]),
-        command_args: &[
-            "gem",
-            "install",
-            "rails",
-            "--no-document",
-            "--no-user-install",
-        ],
+        command_args: &install_rails_args,
         ..Default::default()
     };
 
     let create_app = ExecConfig {
         user: Some(&user),
         work_dir: Some(&work_dir),
-        command_args: &["rails", "new", &name, api, &db],
+        command_args: &create_app_args,
         attach_stdin: Some(true),
         // we need to set this to make sure the gem cache is created
         // inside the container only in order to not polute the host.
@@ -249,6 +516,7 @@ volumes:
             "GEM_SPEC_CACHE=/tmp/gem/cache",
             "HOME=/tmp",
         ]),
+        ..Default::default()
     };
     let project_folder = format!("{}/{}", &work_dir, &name);
     let add_pry = ExecConfig {
@@ -265,6 +533,7 @@ volumes:
             "GEM_SPEC_CACHE=/tmp/gem/cache",
             "HOME=/tmp",
         ]),
+        ..Default::default()
     };
 
     for execution in [
@@ -274,9 +543,12 @@ volumes:
         create_app,
         add_pry,
     ] {
-        let run_result = run_container_command(&docker, &id, execution.clone()).await;
-        if run_result.is_err() {
-            let err_message = run_result.unwrap_err().to_string();
+        let failure = match run_container_command(&docker, &id, execution.clone()).await {
+            Ok(result) if result.success() => None,
+            Ok(result) => Some(result.last_line()),
+            Err(err) => Some(err.to_string()),
+        };
+        if let Some(err_message) = failure {
             remove_container(&id).await;
             println!(
                 "\n[{}] - failed to execute command: {}",
@@ -291,16 +563,15 @@ volumes:
             std::process::exit(1);
         }
     }
-    for config_file in [docker_file, docker_compose_file, (".env", "".to_string())] {
+    for config_file in [docker_file, docker_compose_file, (".env", env_file)] {
         if let Ok(mut file) = File::create(format!("{}/{}", &name, &config_file.0)) {
-            let file_result = file.write_all(config_file.1.as_bytes());
-            if file_result.is_err() {
+            if let Err(err) = file.write_all(config_file.1.as_bytes()) {
                 remove_container(&id).await;
                 println!(
                     "[{}] - Could not write to file: {} - {}",
                     "error".dark_red(),
                     config_file.0.cyan(),
-                    file_result.unwrap_err().to_string().red(),
+                    err.to_string().red(),
                 );
                 std::process::exit(1);
             }